@@ -1,14 +1,11 @@
 extern crate timely;
 extern crate streaming_harness;
 extern crate hdrhist;
-extern crate rand;
 
 use std::collections::HashMap;
-use std::cell::{Cell, RefCell};
+use std::cell::RefCell;
 use std::rc::Rc;
 
-use rand::RngCore;
-
 use timely::dataflow::*;
 use timely::dataflow::operators::{Capability, Probe, Operator, FrontierNotificator};
 use timely::dataflow::operators::flow_controlled;
@@ -16,10 +13,10 @@ use timely::dataflow::channels::pact::Exchange;
 
 use timely::progress::timestamp::RootTimestamp;
 
-use streaming_harness::util::ToNanos;
 use streaming_harness::output;
 use streaming_harness::timeline::Timeline;
 use streaming_harness::timely_support::Acknowledge;
+use streaming_harness::load::{LoadPhase, LoadSummary};
 
 fn main() {
     let mut args = std::env::args();
@@ -27,60 +24,64 @@ fn main() {
 
     // How many seconds.
     let seconds: u64 = args.next().unwrap().parse().unwrap();
-    // How many updates to perform in each round.
+    // How many updates to perform per second, across all workers.
     let throughput: u64 = args.next().unwrap().parse().unwrap();
-    assert_eq!(1_000_000_000 % throughput, 0, "throughput must be a divisor of 1_000_000_000ns");
     // Number of distinct keys.
     let keys: usize = args.next().unwrap().parse().unwrap();
 
-    let timelines: Vec<_> = timely::execute_from_args(args, move |worker| {
+    let results: Vec<_> = timely::execute_from_args(args, move |worker| {
 
         let index = worker.index();
         let peers = worker.peers();
 
-        let (output_metric_collector,) = worker.dataflow(|scope| {
+        let (output_metric_collector, load_phase) = worker.dataflow(|scope| {
             let mut probe_handle = ProbeHandle::new();
 
-            let input_times = || streaming_harness::input::ConstantThroughputInputTimes::<u64, u64>::new(
-                    1, 1_000_000_000 / throughput, seconds * 1_000_000_000);
-            let output_metric_collector = Rc::new(RefCell::new(
+            let input_times = || streaming_harness::input::PartitionedInputTimes::new(
+                streaming_harness::input::ExactRateInputTimes::per_second(
+                    1, throughput, seconds * 1_000_000_000),
+                index, peers);
+            let mut output_metric_collector =
                 streaming_harness::output::default::hdrhist_timeline_collector(
                     input_times(),
-                    0, 2_000_000_000, 8_000_000_000, 10_000_000_000, 1_000_000_000)));
+                    0, 2_000_000_000, 8_000_000_000, 10_000_000_000, 1_000_000_000);
+            output_metric_collector.metrics_mut().set_reporter(move |element|
+                eprintln!("{}", ::streaming_harness::format::format_progress_line(
+                    &format!("progress\t{}", index), element, 1_000_000_000)));
+            let output_metric_collector = Rc::new(RefCell::new(output_metric_collector));
             let output_metric_collector_for_acknowledge = output_metric_collector.clone();
 
-            let data_loaded = ::std::rc::Rc::new(Cell::new(None));
-            { 
+            let load_phase = Rc::new(LoadPhase::new());
+            {
                 let mut loading = true;
-                let data_loaded = data_loaded.clone();
+                let load_phase = load_phase.clone();
 
                 let probe_handle = probe_handle.clone();
 
-                let seed: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, index as u8];
-                let mut rng: ::rand::rngs::SmallRng = ::rand::SeedableRng::from_seed(seed);
+                let mut words = streaming_harness::payload::UniformKeys::new(
+                    streaming_harness::payload::seeded_rng(0x5eed, index), keys as u64);
 
                 let mut input_times = streaming_harness::input::SyntheticInputTimeGenerator::new(input_times());
 
                 flow_controlled::iterator_source(scope, "WordsSource", move |last_ts| {
                     if loading {
                         loading = false;
+                        load_phase.start();
+                        let initial = (0 .. keys / peers).map(|i| ((i * peers + index) as u64, 1)).collect::<Vec<_>>();
+                        load_phase.record_loaded(initial.len());
                         Some(flow_controlled::IteratorSourceInput {
                             lower_bound: RootTimestamp::new(1),
-                            data: vec![
-                                (RootTimestamp::new(0), (0 .. keys / peers).map(|i| ((i * peers + index) as u64, 1)).collect::<Vec<_>>())
-                            ],
+                            data: vec![(RootTimestamp::new(0), initial)],
                             target: RootTimestamp::new(1),
                         })
                     } else {
-                        if data_loaded.get().is_none() {
-                            data_loaded.set(Some(::std::time::Instant::now()));
-                        }
-                        let elapsed = data_loaded.get().as_ref().map(|t| t.elapsed()).unwrap();
-                        let target_ns = (elapsed.to_nanos() + 1) / 1_000_000 * 1_000_000;
+                        load_phase.finish();
+                        let elapsed_ns = load_phase.elapsed_ns().unwrap();
+                        let target_ns = (elapsed_ns + 1) / 1_000_000 * 1_000_000;
                         input_times.iter_until(target_ns).map(|it|
                             flow_controlled::IteratorSourceInput {
                                 lower_bound: RootTimestamp::new(target_ns),
-                                data: vec![(*last_ts, it.map(|ns| (ns, rng.next_u64())).collect::<Vec<_>>())],
+                                data: vec![(*last_ts, it.zip(&mut words).collect::<Vec<_>>())],
                                 target: *last_ts,
                             })
                     }
@@ -90,6 +91,7 @@ fn main() {
                                 |_cap, _| {
                 let mut notificator = FrontierNotificator::new();
                 let mut counts = HashMap::new();
+                #[allow(clippy::mutable_key_type)]
                 let mut stash: HashMap<Capability<_>, Vec<Vec<(u64, u64)>>> = HashMap::new();
 
                 move |input, output| {
@@ -104,15 +106,9 @@ fn main() {
                         let mut data = stash.remove(&time).unwrap();
                         for d in data.drain(..) {
                             for (_t, k) in d.into_iter() {
-                                let mut new_count = 1;
-                                if counts.contains_key(&k) {
-                                    let v = counts.get_mut(&k).unwrap();
-                                    new_count = *v + 1;
-                                    *v = new_count;
-                                } else {
-                                    counts.insert(k.clone(), 1);
-                                }
-                                affected.insert(k, new_count);
+                                let count = counts.entry(k).or_insert(0);
+                                *count += 1;
+                                affected.insert(k, *count);
                             }
                         }
                         output.session(&time).give_iterator(affected.into_iter());
@@ -121,18 +117,28 @@ fn main() {
             })
             .acknowledge(
                 output_metric_collector_for_acknowledge,
-                data_loaded)
+                load_phase.clone())
             .probe_with(&mut probe_handle);
 
-            (output_metric_collector,)
+            (output_metric_collector, load_phase)
         });
 
         while worker.step() { }
 
-        Rc::try_unwrap(output_metric_collector).map_err(|_| ()).expect("dataflow still running").into_inner().into_inner()
+        let mut timeline = Rc::try_unwrap(output_metric_collector).map_err(|_| ()).expect("dataflow still running").into_inner().into_inner();
+        timeline.finish();
+        (timeline, load_phase.summary().expect("data loading did not complete"))
     }).expect("unsuccessful execution").join().into_iter().map(|x| x.unwrap()).collect();
- 
-    let Timeline { timeline, latency_metrics, .. } = output::combine_all(timelines);
+
+    let (timelines, load_summaries): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+    let load_summary = load_summaries.into_iter().reduce(LoadSummary::combined).unwrap();
+    eprintln!("== loading ==\n{} records in {}ns", load_summary.records, load_summary.duration_ns);
+    println!("{}", ::streaming_harness::format::format_load_summary("load".to_string(), load_summary));
+
+    let Timeline { timeline, latency_metrics, underflow, overflow, .. } = output::combine_all(timelines);
+    if underflow.samples > 0 || overflow.samples > 0 {
+        eprintln!("warning: {} samples before and {} samples after the timeline", underflow.samples, overflow.samples);
+    }
 
     eprintln!("== summary ==\n{}", latency_metrics.into_inner().summary_string());
     eprintln!("== timeline ==\n{}",
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use histogram::{Distribution, interval_quantile};
+use input::{VariableRateInputTimes, RecordingInputTimes, RecordedInputTimes, recorded};
+use output::Metrics;
+use timeline::TimelineElement;
+
+/// Source-side input times that follow an `AdaptiveRateController`.
+pub type AdaptiveInputTimes = RecordingInputTimes<u64, VariableRateInputTimes<Box<dyn Fn(u64)->f64>>>;
+
+/// Adjusts the offered rate during a run to find the highest rate that meets a latency target:
+/// the rate increases by `increase` records/s after each interval within `target_latency`, and
+/// is multiplied by `backoff` after each interval that exceeds it, within the rate limits.
+///
+/// Clones share the same rate, so one can be fed latencies (e.g. from a `Timeline` reporter)
+/// while another one reports the current rate.
+#[derive(Debug, Clone)]
+pub struct AdaptiveRateController {
+    rate: Arc<AtomicU64>,
+    target_latency: u64,
+    increase: f64,
+    backoff: f64,
+    min_rate: f64,
+    max_rate: f64,
+}
+
+impl AdaptiveRateController {
+    pub fn new(initial_rate: f64, target_latency: u64, increase: f64, backoff: f64) -> Self {
+        assert!(initial_rate > 0f64, "initial_rate must be positive");
+        assert!(increase >= 0f64, "increase must not be negative");
+        assert!(backoff > 0f64 && backoff < 1f64, "backoff must be in (0, 1)");
+        Self {
+            rate: Arc::new(AtomicU64::new(initial_rate.to_bits())),
+            target_latency,
+            increase,
+            backoff,
+            min_rate: 1f64,
+            max_rate: f64::INFINITY,
+        }
+    }
+
+    /// Keeps the rate within `[min_rate, max_rate]` (by default at least 1 record/s), e.g. so
+    /// that repeated backoffs cannot stall the source.
+    pub fn with_rate_limits(mut self, min_rate: f64, max_rate: f64) -> Self {
+        assert!(min_rate > 0f64 && min_rate <= max_rate, "rate limits must satisfy 0 < min_rate <= max_rate");
+        self.min_rate = min_rate;
+        self.max_rate = max_rate;
+        let rate = self.rate().clamp(min_rate, max_rate);
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+        self
+    }
+
+    /// The current rate, in records per second.
+    pub fn rate(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    /// Updates the rate given the latency measured in the last interval, and returns it.
+    pub fn observe(&mut self, latency: u64) -> f64 {
+        let rate = if latency <= self.target_latency {
+            self.rate() + self.increase
+        } else {
+            self.rate() * self.backoff
+        }.clamp(self.min_rate, self.max_rate);
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+        rate
+    }
+
+    /// Uses the upper bound of the 99th percentile as the interval's latency; empty intervals
+    /// leave the rate unchanged.
+    pub fn observe_element<M: Metrics<u64>+Distribution>(&mut self, element: &TimelineElement<u64, M>) -> f64 {
+        match interval_quantile(element, 0.99) {
+            Some(p99) => self.observe(p99),
+            None => self.rate(),
+        }
+    }
+
+    /// Arrivals (in ns) at the controller's current rate, as a pair of input times for the source
+    /// and for the `MetricCollector` (see `input::recorded`).
+    pub fn input_times(&self, first: u64, end: u64) -> (AdaptiveInputTimes, RecordedInputTimes<u64>) {
+        let rate = self.rate.clone();
+        let rate: Box<dyn Fn(u64)->f64> = Box::new(move |_| f64::from_bits(rate.load(Ordering::Relaxed)));
+        recorded(VariableRateInputTimes::new(first, rate, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_stays_within_limits() {
+        let mut controller = AdaptiveRateController::new(100f64, 1_000, 50f64, 0.5).with_rate_limits(40f64, 180f64);
+        assert_eq!(controller.observe(10), 150f64);
+        assert_eq!(controller.observe(10), 180f64);
+        for _ in 0..10 {
+            controller.observe(10_000);
+        }
+        assert_eq!(controller.rate(), 40f64);
+    }
+}
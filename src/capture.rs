@@ -0,0 +1,316 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use output::{Metrics, CombineError};
+
+/// Keeps the raw `(begin_t, end_t)` pairs, in recording order, for offline analysis.
+///
+/// Each sample is encoded as the zigzag varint of the difference between its `begin_t` and the
+/// previous sample's (0 for the first sample), followed by the varint of its latency; varints
+/// hold 7 bits per byte, least significant first, with the top bit set on all but the last byte.
+/// This takes a few bytes per sample at steady rates.
+///
+/// With a spill file, the buffer is appended to the file whenever it exceeds
+/// `max_buffered_bytes`; the file belongs to the `RawSamples` and is removed when it is dropped
+/// (unless it is `persist`ed), so each worker needs its own path. A failed spill is reported by
+/// `flush`, `samples` and `try_combined`, and the samples stay buffered in memory.
+pub struct RawSamples {
+    sample_every: u64,
+    seen: u64,
+    captured: u64,
+    prev_begin_t: u64,
+    buffer: Vec<u8>,
+    spill: Option<Spill>,
+    error: Option<io::Error>,
+}
+
+struct Spill {
+    path: PathBuf,
+    file: File,
+    max_buffered_bytes: usize,
+}
+
+impl RawSamples {
+    /// Keeps every `sample_every`-th sample in memory.
+    pub fn new(sample_every: u64) -> Self {
+        assert!(sample_every > 0, "sample_every must be positive");
+        Self {
+            sample_every,
+            seen: 0,
+            captured: 0,
+            prev_begin_t: 0,
+            buffer: Vec::new(),
+            spill: None,
+            error: None,
+        }
+    }
+
+    /// Like `new`, but spills to the (truncated) file at `path`.
+    pub fn with_spill_file(sample_every: u64, path: impl Into<PathBuf>, max_buffered_bytes: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().write(true).create(true).truncate(true).open(&path)?;
+        let mut samples = Self::new(sample_every);
+        samples.spill = Some(Spill {
+            path,
+            file,
+            max_buffered_bytes,
+        });
+        Ok(samples)
+    }
+
+    /// Samples offered to `record`, including the ones that were not kept.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// Samples that were kept.
+    pub fn captured(&self) -> u64 {
+        self.captured
+    }
+
+    /// Bytes used by the captured samples, in memory and spilled.
+    pub fn encoded_bytes(&self) -> io::Result<u64> {
+        let spilled = match self.spill {
+            Some(ref spill) => spill.file.metadata()?.len(),
+            None => 0,
+        };
+        Ok(spilled + self.buffer.len() as u64)
+    }
+
+    /// The captured `(begin_t, end_t)` pairs, in recording order; spilled samples are read back
+    /// from the file as the iterator advances.
+    pub fn samples(&self) -> io::Result<RawSamplesIter<'_>> {
+        self.check()?;
+        let spilled: Box<dyn Read+'_> = match self.spill {
+            Some(ref spill) => Box::new(File::open(&spill.path)?),
+            None => Box::new(io::empty()),
+        };
+        Ok(RawSamplesIter {
+            reader: BufReader::new(Box::new(spilled.chain(&self.buffer[..]))),
+            remaining: Some(self.captured),
+            prev_begin_t: 0,
+        })
+    }
+
+    /// Writes the buffered samples to the spill file, if any; also reports an earlier failed
+    /// spill.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.check()?;
+        if let Some(ref mut spill) = self.spill {
+            spill.file.write_all(&self.buffer)?;
+            spill.file.flush()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Keeps the encoded samples in the file at `path`, to be read back with `read_file`.
+    pub fn persist(mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.flush()?;
+        match self.spill {
+            Some(ref spill) => {
+                // renaming fails across file systems
+                if ::std::fs::rename(&spill.path, &path).is_err() {
+                    ::std::fs::copy(&spill.path, &path)?;
+                }
+            },
+            None => ::std::fs::write(&path, &self.buffer)?,
+        }
+        if let Some(spill) = self.spill.take() {
+            let _ = ::std::fs::remove_file(&spill.path);
+        }
+        Ok(())
+    }
+
+    /// The `(begin_t, end_t)` pairs of a file written by `persist`.
+    pub fn read_file(path: impl AsRef<Path>) -> io::Result<RawSamplesIter<'static>> {
+        Ok(RawSamplesIter {
+            reader: BufReader::new(Box::new(File::open(path)?)),
+            remaining: None,
+            prev_begin_t: 0,
+        })
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.error {
+            Some(ref error) => Err(io::Error::new(error.kind(), format!("failed to spill raw samples: {}", error))),
+            None => Ok(()),
+        }
+    }
+
+    fn push(&mut self, begin_t: u64, end_t: u64) {
+        let delta = begin_t.wrapping_sub(self.prev_begin_t) as i64;
+        write_varint(&mut self.buffer, ((delta << 1) ^ (delta >> 63)) as u64);
+        write_varint(&mut self.buffer, end_t - begin_t);
+        self.prev_begin_t = begin_t;
+        self.captured += 1;
+        if self.error.is_none() {
+            if let Some(ref mut spill) = self.spill {
+                if self.buffer.len() >= spill.max_buffered_bytes {
+                    match spill.file.write_all(&self.buffer) {
+                        Ok(()) => self.buffer.clear(),
+                        Err(error) => self.error = Some(error),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for RawSamples {
+    fn drop(&mut self) {
+        if let Some(ref spill) = self.spill {
+            let _ = ::std::fs::remove_file(&spill.path);
+        }
+    }
+}
+
+impl Metrics<u64> for RawSamples {
+    #[inline(always)]
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)] // `is_multiple_of` needs Rust 1.87
+    fn record(&mut self, begin_t: u64, end_t: u64) {
+        if self.seen % self.sample_every == 0 {
+            self.push(begin_t, end_t);
+        }
+        self.seen += 1;
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Appends `other`'s samples after the ones of `self`.
+    fn try_combined(mut self, other: Self) -> Result<Self, CombineError> {
+        if self.sample_every != other.sample_every {
+            return Err(CombineError::mismatch("sample_every", self.sample_every, other.sample_every));
+        }
+        self.check()?;
+        for sample in other.samples()? {
+            let (begin_t, end_t) = sample?;
+            self.push(begin_t, end_t);
+        }
+        self.seen += other.seen;
+        self.check()?;
+        Ok(self)
+    }
+}
+
+pub struct RawSamplesIter<'a> {
+    reader: BufReader<Box<dyn Read+'a>>,
+    // `None` reads until the end of the input
+    remaining: Option<u64>,
+    prev_begin_t: u64,
+}
+
+impl<'a> Iterator for RawSamplesIter<'a> {
+    type Item = io::Result<(u64, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.remaining {
+            Some(0) => return None,
+            Some(ref mut remaining) => *remaining -= 1,
+            None => match self.reader.fill_buf() {
+                Ok([]) => return None,
+                Ok(_) => (),
+                Err(error) => {
+                    self.remaining = Some(0);
+                    return Some(Err(error));
+                },
+            },
+        }
+        let sample = read_varint(&mut self.reader).and_then(|zigzag| {
+            let delta = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+            let begin_t = self.prev_begin_t.wrapping_add(delta as u64);
+            let latency = read_varint(&mut self.reader)?;
+            self.prev_begin_t = begin_t;
+            Ok((begin_t, begin_t + latency))
+        });
+        if sample.is_err() {
+            self.remaining = Some(0);
+        }
+        Some(sample)
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    let mut byte = [0u8];
+    for shift in (0..64).step_by(7) {
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "malformed raw sample"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        ::std::env::temp_dir().join(format!("streaming-harness-{}-{}", name, ::std::process::id()))
+    }
+
+    fn record_all(samples: &mut RawSamples, pairs: &[(u64, u64)]) {
+        for &(begin_t, end_t) in pairs.iter() {
+            samples.record(begin_t, end_t);
+        }
+    }
+
+    #[test]
+    fn spill_round_trip() {
+        let pairs = (0..1000u64).map(|i| (i * 1_000 + i % 7, i * 1_000 + 500 + i % 13)).collect::<Vec<_>>();
+        let (left_path, right_path) = (temp_path("spill-left"), temp_path("spill-right"));
+        let mut left = RawSamples::with_spill_file(2, &left_path, 64).unwrap();
+        let mut right = RawSamples::with_spill_file(2, &right_path, 64).unwrap();
+        record_all(&mut left, &pairs[..500]);
+        record_all(&mut right, &pairs[500..]);
+        assert!(::std::fs::metadata(&left_path).unwrap().len() > 0);
+        let combined = left.try_combined(right).unwrap();
+        assert!(!right_path.exists());
+        assert_eq!((combined.seen(), combined.captured()), (1000, 500));
+        let read = combined.samples().unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(read, pairs.into_iter().step_by(2).collect::<Vec<_>>());
+        drop(combined);
+        assert!(!left_path.exists());
+    }
+
+    #[test]
+    fn persist_and_read_back() {
+        let pairs = [(10, 15), (20, 21), (5, 300), (1 << 40, (1 << 40) + 7)];
+        let spilled_path = temp_path("persist-spill");
+        let persisted_path = temp_path("persist");
+        let mut samples = RawSamples::with_spill_file(1, &spilled_path, 4).unwrap();
+        record_all(&mut samples, &pairs);
+        samples.persist(&persisted_path).unwrap();
+        assert!(!spilled_path.exists());
+        let read = RawSamples::read_file(&persisted_path).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+        ::std::fs::remove_file(&persisted_path).unwrap();
+        assert_eq!(read, pairs.to_vec());
+    }
+
+    #[test]
+    fn truncated_file() {
+        let path = temp_path("truncated");
+        let mut samples = RawSamples::new(1);
+        record_all(&mut samples, &[(1 << 40, (1 << 40) + 1)]);
+        samples.persist(&path).unwrap();
+        let bytes = ::std::fs::read(&path).unwrap();
+        ::std::fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+        let read = RawSamples::read_file(&path).unwrap().collect::<Vec<_>>();
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].as_ref().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use util::ToNanos;
+
+/// A source of monotonically non-decreasing timestamps, in nanoseconds since the clock's origin.
+pub trait Clock {
+    fn now_ns(&self) -> u64;
+}
+
+/// The operating system's monotonic clock (`std::time::Instant`).
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    origin: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MonotonicClock {
+    #[inline(always)]
+    fn now_ns(&self) -> u64 {
+        self.origin.elapsed().to_nanos()
+    }
+}
+
+/// Reads the CPU's timestamp counter, which is cheaper than `MonotonicClock` at very high rates.
+///
+/// Assumes an invariant TSC that is synchronized across cores, as found on modern x86_64 CPUs.
+#[cfg(target_arch = "x86_64")]
+#[derive(Debug, Clone, Copy)]
+pub struct TscClock {
+    origin: u64,
+    ns_per_tick: f64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl TscClock {
+    /// Calibrates the counter against the monotonic clock for 10ms.
+    pub fn new() -> Self {
+        Self::calibrated(::std::time::Duration::from_millis(10))
+    }
+
+    pub fn calibrated(duration: ::std::time::Duration) -> Self {
+        let start = Instant::now();
+        let start_tsc = rdtsc();
+        while start.elapsed() < duration { }
+        let ticks = rdtsc() - start_tsc;
+        let elapsed = start.elapsed().to_nanos();
+        Self {
+            origin: start_tsc,
+            ns_per_tick: elapsed as f64 / ticks as f64,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for TscClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Clock for TscClock {
+    #[inline(always)]
+    fn now_ns(&self) -> u64 {
+        (rdtsc().saturating_sub(self.origin) as f64 * self.ns_per_tick) as u64
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn rdtsc() -> u64 {
+    unsafe { ::std::arch::x86_64::_rdtsc() }
+}
+
+/// A clock that only moves when told to, for deterministic tests of pacing and acknowledgement.
+///
+/// Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn advance(&self, dt: u64) {
+        self.now.fetch_add(dt, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, now: u64) {
+        assert!(now >= self.now_ns(), "virtual time cannot go backwards");
+        self.now.store(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    #[inline(always)]
+    fn now_ns(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
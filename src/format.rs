@@ -1,24 +1,169 @@
+use std::time::Instant;
 
-#[cfg(feature = "hdrhist-support")]
-pub fn format_detailed_timeline(
+use histogram::Distribution;
+use util::ToNanos;
+
+pub fn format_detailed_timeline<M: ::output::Metrics<u64>+Distribution>(
     prefix: String,
-    timeline: Vec<::timeline::TimelineElement<u64, ::hdrhist::HDRHist>>) -> String {
+    timeline: Vec<::timeline::TimelineElement<u64, M>>) -> String {
     timeline.into_iter().map(|::timeline::TimelineElement { time, metrics, .. }|
         metrics
-            .ccdf_upper_bound()
+            .ccdf_upper_bounds()
+            .into_iter()
             .map(|(value, prob)| format!("{}\t{}\t{}\t{}", prefix, time, value, prob))
             .collect::<Vec<_>>()
             .join("\n")).collect::<Vec<_>>().join("\n")
 }
 
-#[cfg(feature = "hdrhist-support")]
-pub fn format_summary_timeline(
+pub fn format_summary_timeline<M: ::output::Metrics<u64>+Distribution>(
     prefix: String,
-    timeline: Vec<::timeline::TimelineElement<u64, ::hdrhist::HDRHist>>) -> String {
+    timeline: Vec<::timeline::TimelineElement<u64, M>>) -> String {
     timeline.into_iter().map(|::timeline::TimelineElement { time, metrics, .. }|
         format!("{}\t{}\t{}", prefix, time, metrics
-            .summary()
-            .map(|(_, _, upper_bound)| format!("{}", upper_bound))
+            .summary_upper_bounds()
+            .into_iter()
+            .map(|upper_bound| format!("{}", upper_bound))
+            .collect::<Vec<_>>()
+            .join("\t"))).collect::<Vec<_>>().join("\n")
+}
+
+/// Like `format_detailed_timeline`, but for any time type and with times and latencies
+/// converted to `unit`, which is included as the second column.
+pub fn format_detailed_timeline_in<T: Eq+Ord+Copy+::util::ToNanos, M: ::output::Metrics<T>+Distribution>(
+    prefix: String,
+    timeline: Vec<::timeline::TimelineElement<T, M>>,
+    unit: ::util::TimeUnit) -> String {
+    detailed_timeline_in(prefix, timeline, unit, |time| time.to_nanos())
+}
+
+/// Like `format_summary_timeline`, but for any time type and with times and latencies
+/// converted to `unit`, which is included as the second column.
+pub fn format_summary_timeline_in<T: Eq+Ord+Copy+::util::ToNanos, M: ::output::Metrics<T>+Distribution>(
+    prefix: String,
+    timeline: Vec<::timeline::TimelineElement<T, M>>,
+    unit: ::util::TimeUnit) -> String {
+    summary_timeline_in(prefix, timeline, unit, |time| time.to_nanos())
+}
+
+/// Like `format_detailed_timeline_in`, for `Instant` timelines, with times relative to `base`
+/// (e.g. the start of the experiment).
+pub fn format_detailed_timeline_since<M: ::output::Metrics<Instant>+Distribution>(
+    prefix: String,
+    timeline: Vec<::timeline::TimelineElement<Instant, M>>,
+    base: Instant,
+    unit: ::util::TimeUnit) -> String {
+    detailed_timeline_in(prefix, timeline, unit, |time| time.duration_since(base).to_nanos())
+}
+
+/// Like `format_summary_timeline_in`, for `Instant` timelines, with times relative to `base`
+/// (e.g. the start of the experiment).
+pub fn format_summary_timeline_since<M: ::output::Metrics<Instant>+Distribution>(
+    prefix: String,
+    timeline: Vec<::timeline::TimelineElement<Instant, M>>,
+    base: Instant,
+    unit: ::util::TimeUnit) -> String {
+    summary_timeline_in(prefix, timeline, unit, |time| time.duration_since(base).to_nanos())
+}
+
+fn detailed_timeline_in<T: Eq+Ord+Copy, M: ::output::Metrics<T>+Distribution>(
+    prefix: String,
+    timeline: Vec<::timeline::TimelineElement<T, M>>,
+    unit: ::util::TimeUnit,
+    time_nanos: impl Fn(T)->u64) -> String {
+    timeline.into_iter().map(|::timeline::TimelineElement { time, metrics, .. }|
+        metrics
+            .ccdf_upper_bounds()
+            .into_iter()
+            .map(|(value, prob)| format!("{}\t{}\t{}\t{}\t{}",
+                prefix, unit, unit.from_nanos(time_nanos(time)), unit.from_nanos(value), prob))
+            .collect::<Vec<_>>()
+            .join("\n")).collect::<Vec<_>>().join("\n")
+}
+
+fn summary_timeline_in<T: Eq+Ord+Copy, M: ::output::Metrics<T>+Distribution>(
+    prefix: String,
+    timeline: Vec<::timeline::TimelineElement<T, M>>,
+    unit: ::util::TimeUnit,
+    time_nanos: impl Fn(T)->u64) -> String {
+    timeline.into_iter().map(|::timeline::TimelineElement { time, metrics, .. }|
+        format!("{}\t{}\t{}\t{}", prefix, unit, unit.from_nanos(time_nanos(time)), metrics
+            .summary_upper_bounds()
+            .into_iter()
+            .map(|upper_bound| format!("{}", unit.from_nanos(upper_bound)))
             .collect::<Vec<_>>()
             .join("\t"))).collect::<Vec<_>>().join("\n")
 }
+
+pub fn format_phases<M: ::output::Metrics<u64>+Distribution>(
+    prefix: String,
+    phases: Vec<::output::Phase<u64, M>>) -> String {
+    phases.into_iter().map(|::output::Phase { name, start, end, metrics, samples }|
+        format!("{}\t{}\t{}\t{}\t{}\t{}", prefix, name, start, end, samples, metrics
+            .summary_upper_bounds()
+            .into_iter()
+            .map(|upper_bound| format!("{}", upper_bound))
+            .collect::<Vec<_>>()
+            .join("\t"))).collect::<Vec<_>>().join("\n")
+}
+
+/// One line per completed interval: time, samples, rate (samples/s), p50, p99 and max latency.
+pub fn format_progress_line<M: ::output::Metrics<u64>+Distribution>(
+    prefix: &str,
+    element: &::timeline::TimelineElement<u64, M>,
+    timeline_dt: u64) -> String {
+    let ::timeline::TimelineElement { time, ref metrics, samples } = *element;
+    format!("{}\t{}\t{}\t{:.0}\t{}", prefix, time, samples,
+        samples as f64 * 1_000_000_000f64 / timeline_dt as f64,
+        metrics
+            .quantile_upper_bounds(&[0.5, 0.99, 1.0])
+            .into_iter()
+            .map(|upper_bound| format!("{}", upper_bound))
+            .collect::<Vec<_>>()
+            .join("\t"))
+}
+
+pub fn format_load_summary(prefix: String, summary: ::load::LoadSummary) -> String {
+    format!("{}\t{}\t{}\t{}", prefix, summary.duration_ns, summary.records,
+        summary.throughput().map(|t| format!("{}", t)).unwrap_or_else(|| "-".to_string()))
+}
+
+/// Writes one line per captured sample: begin time and end time.
+///
+/// Samples are streamed from the capture (and its spill file), so `out` should be buffered.
+pub fn write_raw_samples(prefix: &str, samples: &::capture::RawSamples, mut out: impl ::std::io::Write) -> ::std::io::Result<()> {
+    for sample in samples.samples()? {
+        let (begin_t, end_t) = sample?;
+        writeln!(out, "{}\t{}\t{}", prefix, begin_t, end_t)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "hdrhist-support"))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use output::Metrics;
+    use timeline::TimelineElement;
+
+    #[test]
+    fn summary_timeline_since_base() {
+        let base = Instant::now();
+        let time = base + Duration::from_millis(2);
+        let mut metrics = ::hdrhist::HDRHist::new();
+        metrics.record(time, time + Duration::from_millis(1));
+        let timeline = vec![TimelineElement { time, metrics, samples: 1 }];
+        let line = format_summary_timeline_since("run".to_string(), timeline, base, ::util::TimeUnit::Milliseconds);
+        assert!(line.starts_with("run\tms\t2\t"), "{}", line);
+    }
+
+    #[test]
+    fn raw_samples_lines() {
+        let mut samples = ::capture::RawSamples::new(1);
+        samples.record(10, 15);
+        samples.record(20, 22);
+        let mut out = Vec::new();
+        write_raw_samples("run", &samples, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "run\t10\t15\nrun\t20\t22\n");
+    }
+}
@@ -0,0 +1,406 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use output::{Metrics, CombineError};
+use timeline::TimelineElement;
+
+/// The quantiles reported by `summary_upper_bounds`, the same as `hdrhist::HDRHist::summary`.
+pub const SUMMARY_QUANTILES: [f64; 7] = [0.25, 0.50, 0.75, 0.95, 0.99, 0.999, 1.0];
+
+/// A latency distribution that the `format` functions can report on.
+///
+/// Backends trade accuracy for memory differently; all of them report upper bounds, so that
+/// quantiles are never underestimated.
+pub trait Distribution {
+    /// Upper bound of each of `quantiles` (in `[0, 1]`), 0 if nothing was recorded.
+    fn quantile_upper_bounds(&self, quantiles: &[f64]) -> Vec<u64>;
+
+    /// Points `(value, fraction)` of an upper bound of the complementary cumulative distribution,
+    /// by increasing value.
+    fn ccdf_upper_bounds(&self) -> Vec<(u64, f64)>;
+
+    fn summary_upper_bounds(&self) -> Vec<u64> {
+        self.quantile_upper_bounds(&SUMMARY_QUANTILES)
+    }
+}
+
+#[cfg(feature = "hdrhist-support")]
+impl Distribution for ::hdrhist::HDRHist {
+    fn quantile_upper_bounds(&self, quantiles: &[f64]) -> Vec<u64> {
+        self.quantiles(quantiles.iter().cloned()).map(|(_, _, upper_bound)| upper_bound).collect()
+    }
+
+    fn ccdf_upper_bounds(&self) -> Vec<(u64, f64)> {
+        self.ccdf_upper_bound().collect()
+    }
+}
+
+/// Upper bound of the `quantile` of an interval's latencies, `None` if the interval is empty.
+pub fn interval_quantile<M: Metrics<u64>+Distribution>(element: &TimelineElement<u64, M>, quantile: f64) -> Option<u64> {
+    if element.samples == 0 {
+        None
+    } else {
+        element.metrics.quantile_upper_bounds(&[quantile]).pop()
+    }
+}
+
+/// Keeps every latency, for exact quantiles in small runs.
+#[derive(Debug, Clone)]
+pub struct SortedSamples {
+    samples: Vec<u64>,
+    sorted: bool,
+}
+
+impl SortedSamples {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            sorted: true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The recorded latencies, in increasing order.
+    pub fn sorted_samples(&mut self) -> &[u64] {
+        if !self.sorted {
+            self.samples.sort_unstable();
+            self.sorted = true;
+        }
+        &self.samples
+    }
+
+    fn sorted(&self) -> Cow<'_, [u64]> {
+        if self.sorted {
+            Cow::Borrowed(&self.samples)
+        } else {
+            let mut samples = self.samples.clone();
+            samples.sort_unstable();
+            Cow::Owned(samples)
+        }
+    }
+}
+
+impl Default for SortedSamples {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics<u64> for SortedSamples {
+    #[inline(always)]
+    #[allow(unknown_lints, clippy::unnecessary_map_or)] // `is_some_and` needs Rust 1.70
+    fn record(&mut self, begin_t: u64, end_t: u64) {
+        let latency = end_t - begin_t;
+        if self.samples.last().map_or(false, |last| *last > latency) {
+            self.sorted = false;
+        }
+        self.samples.push(latency);
+    }
+
+    fn combined(mut self, other: Self) -> Self {
+        self.samples.extend(other.samples);
+        self.sorted = false;
+        self
+    }
+}
+
+impl Distribution for SortedSamples {
+    fn quantile_upper_bounds(&self, quantiles: &[f64]) -> Vec<u64> {
+        let samples = self.sorted();
+        quantiles.iter().map(|q| {
+            if samples.is_empty() {
+                return 0;
+            }
+            let rank = (q * samples.len() as f64).ceil() as usize;
+            samples[rank.clamp(1, samples.len()) - 1]
+        }).collect()
+    }
+
+    fn ccdf_upper_bounds(&self) -> Vec<(u64, f64)> {
+        let samples = self.sorted();
+        let total = samples.len() as f64;
+        let mut ccdf = Vec::new();
+        for (i, value) in samples.iter().enumerate() {
+            if i == 0 || samples[i - 1] != *value {
+                ccdf.push((*value, (samples.len() - i) as f64 / total));
+            }
+        }
+        ccdf
+    }
+}
+
+/// A mergeable sketch in the style of DDSketch, with memory logarithmic in the range of the
+/// recorded latencies: upper bounds exceed the actual value by at most a factor of
+/// `(1 + relative_accuracy) / (1 - relative_accuracy)`.
+#[derive(Debug, Clone)]
+pub struct RelativeErrorSketch {
+    relative_accuracy: f64,
+    log_gamma: f64,
+    zeros: u64,
+    buckets: BTreeMap<i32, u64>,
+    count: u64,
+}
+
+impl RelativeErrorSketch {
+    pub fn new(relative_accuracy: f64) -> Self {
+        assert!(relative_accuracy > 0f64 && relative_accuracy < 1f64, "relative_accuracy must be in (0, 1)");
+        let gamma = (1f64 + relative_accuracy) / (1f64 - relative_accuracy);
+        Self {
+            relative_accuracy,
+            log_gamma: gamma.ln(),
+            zeros: 0,
+            buckets: BTreeMap::new(),
+            count: 0,
+        }
+    }
+
+    /// Bucket `i` holds the values in `(gamma^(i-1), gamma^i]`.
+    fn bucket_index(&self, value: u64) -> i32 {
+        ((value as f64).ln() / self.log_gamma).ceil() as i32
+    }
+
+    fn bucket_upper_bound(&self, index: i32) -> u64 {
+        (index as f64 * self.log_gamma).exp().ceil() as u64
+    }
+
+    fn counts(&self) -> impl Iterator<Item=(u64, u64)>+Clone+'_ {
+        let zeros = if self.zeros > 0 { Some((0, self.zeros)) } else { None };
+        zeros.into_iter().chain(self.buckets.iter().map(move |(index, count)| (self.bucket_upper_bound(*index), *count)))
+    }
+}
+
+impl Metrics<u64> for RelativeErrorSketch {
+    #[inline(always)]
+    fn record(&mut self, begin_t: u64, end_t: u64) {
+        let latency = end_t - begin_t;
+        if latency == 0 {
+            self.zeros += 1;
+        } else {
+            *self.buckets.entry(self.bucket_index(latency)).or_insert(0) += 1;
+        }
+        self.count += 1;
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_combined(mut self, other: Self) -> Result<Self, CombineError> {
+        if self.relative_accuracy != other.relative_accuracy {
+            return Err(CombineError::mismatch("relative_accuracy", self.relative_accuracy, other.relative_accuracy));
+        }
+        for (index, count) in other.buckets {
+            *self.buckets.entry(index).or_insert(0) += count;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+        Ok(self)
+    }
+}
+
+impl Distribution for RelativeErrorSketch {
+    fn quantile_upper_bounds(&self, quantiles: &[f64]) -> Vec<u64> {
+        upper_bounds(self.counts(), self.count, quantiles)
+    }
+
+    fn ccdf_upper_bounds(&self) -> Vec<(u64, f64)> {
+        ccdf_upper_bounds(self.counts(), self.count)
+    }
+}
+
+/// A histogram with a fixed set of buckets, each power of two split into `2^precision_bits`
+/// linear sub-buckets; `precision_bits` of 4 matches `hdrhist::HDRHist`.
+#[derive(Debug, Clone)]
+pub struct LogLinearHistogram {
+    precision_bits: usize,
+    counts: Vec<u64>,
+    count: u64,
+}
+
+impl LogLinearHistogram {
+    pub fn new(precision_bits: usize) -> Self {
+        assert!(precision_bits > 0 && precision_bits <= 16, "precision_bits must be in [1, 16]");
+        Self {
+            precision_bits,
+            counts: vec![0; bucket_count(precision_bits)],
+            count: 0,
+        }
+    }
+
+    fn counts(&self) -> impl Iterator<Item=(u64, u64)>+Clone+'_ {
+        self.counts.iter().enumerate().filter(|&(_, count)| *count > 0)
+            .map(move |(bucket, count)| (bucket_value(bucket + 1, self.precision_bits), *count))
+    }
+}
+
+impl Metrics<u64> for LogLinearHistogram {
+    #[inline(always)]
+    fn record(&mut self, begin_t: u64, end_t: u64) {
+        self.counts[bucket_index(end_t - begin_t, self.precision_bits)] += 1;
+        self.count += 1;
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_combined(mut self, other: Self) -> Result<Self, CombineError> {
+        if self.precision_bits != other.precision_bits {
+            return Err(CombineError::mismatch("precision_bits", self.precision_bits, other.precision_bits));
+        }
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts) {
+            *count += other_count;
+        }
+        self.count += other.count;
+        Ok(self)
+    }
+}
+
+impl Distribution for LogLinearHistogram {
+    fn quantile_upper_bounds(&self, quantiles: &[f64]) -> Vec<u64> {
+        upper_bounds(self.counts(), self.count, quantiles)
+    }
+
+    fn ccdf_upper_bounds(&self) -> Vec<(u64, f64)> {
+        ccdf_upper_bounds(self.counts(), self.count)
+    }
+}
+
+// `counts` are `(upper bound, count)` pairs by increasing upper bound
+fn upper_bounds(counts: impl Iterator<Item=(u64, u64)>+Clone, total: u64, quantiles: &[f64]) -> Vec<u64> {
+    quantiles.iter().map(|q| {
+        let rank = ((q * total as f64).ceil() as u64).max(1);
+        let mut sum = 0;
+        for (upper_bound, count) in counts.clone() {
+            sum += count;
+            if sum >= rank {
+                return upper_bound;
+            }
+        }
+        counts.clone().last().map_or(0, |(upper_bound, _)| upper_bound)
+    }).collect()
+}
+
+// each upper bound is paired with the fraction of samples in its bucket or above
+fn ccdf_upper_bounds(counts: impl Iterator<Item=(u64, u64)>, total: u64) -> Vec<(u64, f64)> {
+    let mut above = total;
+    counts.map(|(upper_bound, count)| {
+        let point = (upper_bound, above as f64 / total as f64);
+        above -= count;
+        point
+    }).collect()
+}
+
+pub(crate) fn bucket_count(precision_bits: usize) -> usize {
+    (64 - precision_bits + 1) << precision_bits
+}
+
+pub(crate) fn bucket_index(value: u64, precision_bits: usize) -> usize {
+    let msb = 64usize - value.leading_zeros() as usize;
+    let index = msb.saturating_sub(precision_bits);
+    let low_bits = (value >> index.saturating_sub(1)) as usize & ((1 << precision_bits) - 1);
+    (index << precision_bits) + low_bits
+}
+
+// smallest value in the bucket
+pub(crate) fn bucket_value(bucket: usize, precision_bits: usize) -> u64 {
+    if bucket >= bucket_count(precision_bits) {
+        return u64::MAX;
+    }
+    let index = bucket >> precision_bits;
+    let sub = (bucket & ((1 << precision_bits) - 1)) as u64;
+    if index > 0 {
+        (1u64 << (index + precision_bits - 1)).saturating_add(sub << (index - 1))
+    } else {
+        sub
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUANTILES: [f64; 9] = [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 0.999, 1.0];
+
+    // latencies spread over many orders of magnitude, from a fixed linear congruential generator
+    fn latencies(count: usize) -> Vec<u64> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..count).map(|_| {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) >> (state >> 58)
+        }).collect()
+    }
+
+    fn recorded<M: Metrics<u64>>(mut metrics: M, latencies: &[u64]) -> M {
+        for latency in latencies.iter() {
+            metrics.record(0, *latency);
+        }
+        metrics
+    }
+
+    fn true_quantiles(latencies: &[u64]) -> Vec<u64> {
+        recorded(SortedSamples::new(), latencies).quantile_upper_bounds(&QUANTILES)
+    }
+
+    #[test]
+    fn bucket_bounds_contain_their_values() {
+        for precision_bits in [1, 4, 7].iter().cloned() {
+            for value in latencies(10_000).into_iter().chain(vec![0, 1, 2, 15, 16, 17, u64::MAX]) {
+                let bucket = bucket_index(value, precision_bits);
+                assert!(bucket < bucket_count(precision_bits));
+                assert!(bucket_value(bucket, precision_bits) <= value, "{} in bucket {}", value, bucket);
+                assert!(value < bucket_value(bucket + 1, precision_bits) || value == u64::MAX, "{} in bucket {}", value, bucket);
+            }
+        }
+    }
+
+    #[test]
+    fn log_linear_upper_bounds() {
+        let latencies = latencies(10_000);
+        let expected = true_quantiles(&latencies);
+        for precision_bits in [1, 4, 7].iter().cloned() {
+            let bounds = recorded(LogLinearHistogram::new(precision_bits), &latencies).quantile_upper_bounds(&QUANTILES);
+            for ((quantile, bound), value) in QUANTILES.iter().zip(bounds).zip(expected.iter()) {
+                assert!(bound >= *value, "p{} with {} bits: {} < {}", quantile, precision_bits, bound, value);
+            }
+        }
+    }
+
+    #[test]
+    fn relative_error_sketch_upper_bounds() {
+        let latencies = latencies(10_000);
+        let expected = true_quantiles(&latencies);
+        for relative_accuracy in [0.01, 0.05, 0.2].iter().cloned() {
+            let bounds = recorded(RelativeErrorSketch::new(relative_accuracy), &latencies).quantile_upper_bounds(&QUANTILES);
+            let max_factor = (1f64 + relative_accuracy) / (1f64 - relative_accuracy);
+            for ((quantile, bound), value) in QUANTILES.iter().zip(bounds).zip(expected.iter()) {
+                assert!(bound >= *value, "p{} at {}: {} < {}", quantile, relative_accuracy, bound, value);
+                assert!(bound as f64 <= (*value as f64 * max_factor).ceil() + 1f64,
+                        "p{} at {}: {} too far above {}", quantile, relative_accuracy, bound, value);
+            }
+        }
+    }
+
+    #[cfg(feature = "hdrhist-support")]
+    #[test]
+    fn log_linear_matches_hdrhist() {
+        let latencies = latencies(10_000);
+        let log_linear = recorded(LogLinearHistogram::new(4), &latencies);
+        let hdrhist = recorded(::hdrhist::HDRHist::new(), &latencies);
+        // `HDRHist` reports 0 for the 0th percentile whatever was recorded
+        let quantiles = &QUANTILES[1..];
+        assert_eq!(log_linear.quantile_upper_bounds(quantiles), hdrhist.quantile_upper_bounds(quantiles));
+        // `HDRHist` also reports the empty buckets, which repeat the fraction of the bucket above them
+        let hdrhist_ccdf = hdrhist.ccdf_upper_bounds();
+        let mut nonempty = hdrhist_ccdf.windows(2).filter(|pair| pair[0].1 != pair[1].1).map(|pair| pair[0]).collect::<Vec<_>>();
+        nonempty.extend(hdrhist_ccdf.last());
+        assert_eq!(log_linear.ccdf_upper_bounds(), nonempty);
+    }
+}
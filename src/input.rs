@@ -1,10 +1,15 @@
-use std::ops::Add;
+use std::ops::{Add, Sub};
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 
 pub trait InputTimeResumableIterator<T: Eq+Ord>: Iterator<Item=T> {
-    #[inline(always)]
     fn peek(&mut self) -> Option<&T>;
-    #[inline(always)]
     fn end(&self) -> bool;
+
+    /// Called by `MetricCollector` when the input scheduled at `input_t` is acknowledged `at`.
+    #[inline(always)]
+    fn acknowledged(&mut self, _input_t: T, _at: T) { }
 }
 
 pub struct ConstantThroughputInputTimes<T: Copy+Eq+Ord+Add<DT, Output=T>, DT> {
@@ -53,8 +58,870 @@ impl<T: Copy+Eq+Ord+Add<DT, Output=T>, DT: Copy> InputTimeResumableIterator<T> f
     }
 }
 
+/// Splits a global arrival process among workers, so that the aggregate rate across all workers
+/// is that of the global process.
+///
+/// Arrivals are dealt in rounds of `shares.iter().sum()`: worker `i` gets `shares[i]` consecutive
+/// arrivals of each round.
+pub struct PartitionedInputTimes<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> {
+    input_times: I,
+    position: usize,
+    share_start: usize,
+    share_end: usize,
+    period: usize,
+    _phantom_data: ::std::marker::PhantomData<T>,
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> PartitionedInputTimes<T, I> {
+    /// Worker `index` of `peers` gets every `peers`-th arrival.
+    pub fn new(input_times: I, index: usize, peers: usize) -> Self {
+        assert!(index < peers, "index must be less than peers");
+        Self::with_shares(input_times, index, &vec![1; peers])
+    }
+
+    pub fn with_shares(input_times: I, index: usize, shares: &[usize]) -> Self {
+        assert!(index < shares.len(), "index must be less than shares.len()");
+        let share_start = shares[..index].iter().sum();
+        let period = shares.iter().sum();
+        assert!(period > 0, "at least one share must be positive");
+        Self {
+            input_times,
+            position: 0,
+            share_start,
+            share_end: share_start + shares[index],
+            period,
+            _phantom_data: ::std::marker::PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    fn skip_others(&mut self) {
+        loop {
+            let offset = self.position % self.period;
+            if offset >= self.share_start && offset < self.share_end {
+                break;
+            }
+            if self.input_times.next().is_none() {
+                break;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> Iterator for PartitionedInputTimes<T, I> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.skip_others();
+        let next = self.input_times.next();
+        if next.is_some() {
+            self.position += 1;
+        }
+        next
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> InputTimeResumableIterator<T> for PartitionedInputTimes<T, I> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        self.skip_others();
+        self.input_times.peek()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.input_times.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, input_t: T, at: T) {
+        self.input_times.acknowledged(input_t, at);
+    }
+}
+
+// longest step over which the rate is assumed to be constant
+const MAX_RATE_STEP_NS: f64 = 1_000_000f64;
+
+/// A rate (records/s) at time `t` (ns) of `mean + amplitude * sin(2 * pi * t / period)`, e.g. to
+/// compress a daily traffic cycle into a few minutes.
+pub fn sinusoidal_rate(mean: f64, amplitude: f64, period: u64) -> impl Fn(u64)->f64 {
+    assert!(period > 0, "period must be positive");
+    move |t| mean + amplitude * (2f64 * ::std::f64::consts::PI * (t % period) as f64 / period as f64).sin()
+}
+
+/// Arrivals (in ns) whose rate follows `rate(t)` in records per second, which can be any curve,
+/// e.g. `sinusoidal_rate`. Non-positive rates mean no arrivals.
+pub struct VariableRateInputTimes<F: Fn(u64)->f64> {
+    rate: F,
+    next: f64,
+    end: u64,
+    peeked: u64,
+}
+
+impl<F: Fn(u64)->f64> VariableRateInputTimes<F> {
+    pub fn new(first: u64, rate: F, end: u64) -> Self {
+        let mut input_times = Self {
+            rate,
+            next: first as f64,
+            end,
+            peeked: first,
+        };
+        while input_times.next < end as f64 && (input_times.rate)(input_times.next as u64) <= 0f64 {
+            input_times.next += MAX_RATE_STEP_NS;
+        }
+        input_times.peeked = input_times.next as u64;
+        input_times
+    }
+
+    /// Moves to the time at which the integral of the rate from the current arrival reaches
+    /// `arrivals`.
+    #[inline(always)]
+    fn advance(&mut self, mut arrivals: f64) {
+        while self.next < self.end as f64 {
+            let rate = (self.rate)(self.next as u64).max(0f64);
+            let dt = arrivals * 1_000_000_000f64 / rate;
+            if dt <= MAX_RATE_STEP_NS {
+                self.next += dt;
+                break;
+            }
+            arrivals -= rate * MAX_RATE_STEP_NS / 1_000_000_000f64;
+            self.next += MAX_RATE_STEP_NS;
+        }
+        self.peeked = self.next as u64;
+    }
+}
+
+impl<F: Fn(u64)->f64> Iterator for VariableRateInputTimes<F> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        if !self.end() {
+            let n = self.peeked;
+            self.advance(1f64);
+            Some(n)
+        } else {
+            None
+        }
+    }
+}
+
+impl<F: Fn(u64)->f64> InputTimeResumableIterator<u64> for VariableRateInputTimes<F> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&u64> {
+        if !self.end() {
+            Some(&self.peeked)
+        } else {
+            None
+        }
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.peeked >= self.end
+    }
+}
+
+/// Like `VariableRateInputTimes`, but with exponentially distributed inter-arrival times, i.e. a
+/// Poisson process whose rate follows `rate(t)`.
+///
+/// The source and the `MetricCollector` each build their own copy of the input times, and the
+/// two only draw the same arrivals from identically seeded rngs (e.g. `payload::seeded_rng`),
+/// never from `thread_rng()`. Alternatively, build a single copy and split it with `recorded`.
+#[cfg(feature = "rand-support")]
+pub struct PoissonInputTimes<F: Fn(u64)->f64, R: ::rand::Rng> {
+    input_times: VariableRateInputTimes<F>,
+    rng: R,
+}
+
+#[cfg(feature = "rand-support")]
+impl<F: Fn(u64)->f64, R: ::rand::Rng> PoissonInputTimes<F, R> {
+    pub fn new(first: u64, rate: F, end: u64, rng: R) -> Self {
+        Self {
+            input_times: VariableRateInputTimes::new(first, rate, end),
+            rng,
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<F: Fn(u64)->f64, R: ::rand::Rng> Iterator for PoissonInputTimes<F, R> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        if !self.end() {
+            let n = self.input_times.peeked;
+            // 1 - u is in (0, 1], so the logarithm is finite
+            let u: f64 = self.rng.gen();
+            self.input_times.advance(-(1f64 - u).ln());
+            Some(n)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<F: Fn(u64)->f64, R: ::rand::Rng> InputTimeResumableIterator<u64> for PoissonInputTimes<F, R> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&u64> {
+        self.input_times.peek()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.input_times.end()
+    }
+}
+
+/// The number of records in each batch of a `BatchedInputTimes`.
+pub trait BatchSize {
+    fn next_batch_size(&mut self) -> usize;
+}
+
+impl BatchSize for usize {
+    #[inline(always)]
+    fn next_batch_size(&mut self) -> usize {
+        *self
+    }
+}
+
+impl<F: FnMut()->usize> BatchSize for F {
+    #[inline(always)]
+    fn next_batch_size(&mut self) -> usize {
+        self()
+    }
+}
+
+/// Batch sizes drawn uniformly from `[min, max]`.
+///
+/// The source's and the collector's `BatchedInputTimes` each draw their own batch sizes, so
+/// their `rng`s must be seeded alike, e.g. with `payload::seeded_rng`.
+#[cfg(feature = "rand-support")]
+pub struct UniformBatchSize<R: ::rand::Rng> {
+    rng: R,
+    min: usize,
+    max: usize,
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: ::rand::Rng> UniformBatchSize<R> {
+    pub fn new(rng: R, min: usize, max: usize) -> Self {
+        assert!(min <= max, "min must not exceed max");
+        Self {
+            rng,
+            min,
+            max,
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: ::rand::Rng> BatchSize for UniformBatchSize<R> {
+    #[inline(always)]
+    fn next_batch_size(&mut self) -> usize {
+        self.rng.gen_range(self.min, self.max + 1)
+    }
+}
+
+/// Turns each arrival into a batch of records scheduled at the same time, so that the
+/// `MetricCollector` records a latency for every record of the batch. Empty batches are skipped.
+pub struct BatchedInputTimes<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>, B: BatchSize> {
+    input_times: I,
+    batch_size: B,
+    current: Option<T>,
+    remaining: usize,
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>, B: BatchSize> BatchedInputTimes<T, I, B> {
+    pub fn new(input_times: I, batch_size: B) -> Self {
+        Self {
+            input_times,
+            batch_size,
+            current: None,
+            remaining: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn fill(&mut self) {
+        while self.remaining == 0 {
+            match self.input_times.next() {
+                Some(t) => {
+                    self.current = Some(t);
+                    self.remaining = self.batch_size.next_batch_size();
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>, B: BatchSize> Iterator for BatchedInputTimes<T, I, B> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.fill();
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>, B: BatchSize> InputTimeResumableIterator<T> for BatchedInputTimes<T, I, B> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        self.fill();
+        if self.remaining > 0 {
+            self.current.as_ref()
+        } else {
+            None
+        }
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.remaining == 0 && self.input_times.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, input_t: T, at: T) {
+        self.input_times.acknowledged(input_t, at);
+    }
+}
+
+/// Merges two arrival processes in time order.
+pub struct MergedInputTimes<T: Copy+Eq+Ord, A: InputTimeResumableIterator<T>, B: InputTimeResumableIterator<T>> {
+    a: A,
+    b: B,
+    // the side of the last arrival, which is the one that gets its acknowledgement
+    last_from_a: bool,
+    _phantom_data: ::std::marker::PhantomData<T>,
+}
+
+impl<T: Copy+Eq+Ord, A: InputTimeResumableIterator<T>, B: InputTimeResumableIterator<T>> MergedInputTimes<T, A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self {
+            a,
+            b,
+            last_from_a: true,
+            _phantom_data: ::std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Copy+Eq+Ord, A: InputTimeResumableIterator<T>, B: InputTimeResumableIterator<T>> Iterator for MergedInputTimes<T, A, B> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.last_from_a = match (self.a.peek().cloned(), self.b.peek().cloned()) {
+            (Some(a), Some(b)) => a <= b,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return None,
+        };
+        if self.last_from_a { self.a.next() } else { self.b.next() }
+    }
+}
+
+impl<T: Copy+Eq+Ord, A: InputTimeResumableIterator<T>, B: InputTimeResumableIterator<T>> InputTimeResumableIterator<T> for MergedInputTimes<T, A, B> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        match (self.a.peek().cloned(), self.b.peek().cloned()) {
+            (Some(a), Some(b)) => if a <= b { self.a.peek() } else { self.b.peek() },
+            (Some(_), None) => self.a.peek(),
+            (None, Some(_)) => self.b.peek(),
+            (None, None) => None,
+        }
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.a.end() && self.b.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, input_t: T, at: T) {
+        if self.last_from_a {
+            self.a.acknowledged(input_t, at);
+        } else {
+            self.b.acknowledged(input_t, at);
+        }
+    }
+}
+
+/// Shifts every arrival by `offset`.
+pub struct OffsetInputTimes<T: Copy+Eq+Ord+Add<DT, Output=T>, DT: Copy, I: InputTimeResumableIterator<T>> {
+    input_times: I,
+    offset: DT,
+    peeked: Option<T>,
+}
+
+impl<T: Copy+Eq+Ord+Add<DT, Output=T>, DT: Copy, I: InputTimeResumableIterator<T>> OffsetInputTimes<T, DT, I> {
+    pub fn new(input_times: I, offset: DT) -> Self {
+        Self {
+            input_times,
+            offset,
+            peeked: None,
+        }
+    }
+}
+
+impl<T: Copy+Eq+Ord+Add<DT, Output=T>, DT: Copy, I: InputTimeResumableIterator<T>> Iterator for OffsetInputTimes<T, DT, I> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.input_times.next().map(|t| t + self.offset)
+    }
+}
+
+impl<T: Copy+Eq+Ord+Add<DT, Output=T>+Sub<DT, Output=T>, DT: Copy, I: InputTimeResumableIterator<T>> InputTimeResumableIterator<T> for OffsetInputTimes<T, DT, I> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        let offset = self.offset;
+        self.peeked = self.input_times.peek().map(|t| *t + offset);
+        self.peeked.as_ref()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.input_times.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, input_t: T, at: T) {
+        self.input_times.acknowledged(input_t - self.offset, at - self.offset);
+    }
+}
+
+/// Multiplies the time between each arrival and the first one by `factor`: a factor of 2 halves
+/// the rate.
+pub struct ScaledInputTimes<I: InputTimeResumableIterator<u64>> {
+    input_times: I,
+    factor: f64,
+    origin: Option<u64>,
+    peeked: Option<u64>,
+    last: u64,
+}
+
+impl<I: InputTimeResumableIterator<u64>> ScaledInputTimes<I> {
+    pub fn new(input_times: I, factor: f64) -> Self {
+        assert!(factor > 0f64, "factor must be positive");
+        Self {
+            input_times,
+            factor,
+            origin: None,
+            peeked: None,
+            last: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn scale(&mut self, t: u64) -> u64 {
+        let origin = *self.origin.get_or_insert(t);
+        origin + ((t - origin) as f64 * self.factor).round() as u64
+    }
+
+    #[inline(always)]
+    fn unscale(&self, t: u64) -> u64 {
+        let origin = self.origin.unwrap_or(0);
+        origin + (t.saturating_sub(origin) as f64 / self.factor).round() as u64
+    }
+}
+
+impl<I: InputTimeResumableIterator<u64>> Iterator for ScaledInputTimes<I> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        let t = self.input_times.next()?;
+        self.last = t;
+        Some(self.scale(t))
+    }
+}
+
+impl<I: InputTimeResumableIterator<u64>> InputTimeResumableIterator<u64> for ScaledInputTimes<I> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&u64> {
+        self.peeked = self.input_times.peek().cloned().map(|t| self.scale(t));
+        self.peeked.as_ref()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.input_times.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, _input_t: u64, at: u64) {
+        // scaled times are rounded, so the arrival is the last unscaled one rather than mapped back
+        let (last, at) = (self.last, self.unscale(at));
+        self.input_times.acknowledged(last, at);
+    }
+}
+
+/// Ends an arrival process at a given time and/or after a given number of arrivals.
+pub struct TruncatedInputTimes<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> {
+    input_times: I,
+    until: Option<T>,
+    remaining: Option<usize>,
+    ended: bool,
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> TruncatedInputTimes<T, I> {
+    /// Drops arrivals at or after `until`.
+    pub fn until(input_times: I, until: T) -> Self {
+        Self::new(input_times, Some(until), None)
+    }
+
+    /// Keeps the first `count` arrivals.
+    pub fn take(input_times: I, count: usize) -> Self {
+        Self::new(input_times, None, Some(count))
+    }
+
+    pub fn new(input_times: I, until: Option<T>, count: Option<usize>) -> Self {
+        Self {
+            input_times,
+            until,
+            remaining: count,
+            ended: false,
+        }
+    }
+
+    #[inline(always)]
+    fn check_end(&mut self) -> bool {
+        if !self.ended {
+            let past_until = match (self.until, self.input_times.peek()) {
+                (Some(until), Some(t)) => *t >= until,
+                _ => false,
+            };
+            self.ended = past_until || self.remaining == Some(0);
+        }
+        self.ended
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> Iterator for TruncatedInputTimes<T, I> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        if self.check_end() {
+            return None;
+        }
+        let next = self.input_times.next();
+        if next.is_some() {
+            if let Some(ref mut remaining) = self.remaining {
+                *remaining -= 1;
+            }
+        }
+        next
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> InputTimeResumableIterator<T> for TruncatedInputTimes<T, I> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        if self.check_end() {
+            None
+        } else {
+            self.input_times.peek()
+        }
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.ended || self.remaining == Some(0) || self.input_times.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, input_t: T, at: T) {
+        self.input_times.acknowledged(input_t, at);
+    }
+}
+
+/// Delays each arrival by a uniformly random amount in `[0, max_jitter]`, without reordering
+/// arrivals.
+///
+/// The jitter is part of the schedule: the copies for the source and for the `MetricCollector`
+/// only agree if their `rng`s start from the same seed (see `PoissonInputTimes`).
+#[cfg(feature = "rand-support")]
+pub struct JitteredInputTimes<R: ::rand::Rng, I: InputTimeResumableIterator<u64>> {
+    input_times: I,
+    rng: R,
+    max_jitter: u64,
+    last: u64,
+    last_unjittered: u64,
+    peeked: Option<u64>,
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: ::rand::Rng, I: InputTimeResumableIterator<u64>> JitteredInputTimes<R, I> {
+    pub fn new(input_times: I, rng: R, max_jitter: u64) -> Self {
+        Self {
+            input_times,
+            rng,
+            max_jitter,
+            last: 0,
+            last_unjittered: 0,
+            peeked: None,
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: ::rand::Rng, I: InputTimeResumableIterator<u64>> Iterator for JitteredInputTimes<R, I> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        self.peek()?;
+        self.last_unjittered = self.input_times.next().unwrap();
+        self.last = self.peeked.take().unwrap();
+        Some(self.last)
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: ::rand::Rng, I: InputTimeResumableIterator<u64>> InputTimeResumableIterator<u64> for JitteredInputTimes<R, I> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&u64> {
+        if self.peeked.is_none() {
+            if let Some(&t) = self.input_times.peek() {
+                let jitter = self.rng.gen_range(0, self.max_jitter + 1);
+                self.peeked = Some(::std::cmp::max(self.last, t + jitter));
+            }
+        }
+        self.peeked.as_ref()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.input_times.end()
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, _input_t: u64, at: u64) {
+        self.input_times.acknowledged(self.last_unjittered, at);
+    }
+}
+
+struct RecordedState<T> {
+    issued: VecDeque<T>,
+    ended: bool,
+}
+
+/// Records the arrivals that the source actually issued and replays them to the
+/// `MetricCollector`, for arrival processes whose schedule changes while the experiment runs.
+///
+/// The first end wraps the source's input times, the second end goes in the `MetricCollector`;
+/// both must be in the same thread.
+pub fn recorded<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>>(input_times: I) -> (RecordingInputTimes<T, I>, RecordedInputTimes<T>) {
+    let state = Rc::new(RefCell::new(RecordedState {
+        issued: VecDeque::new(),
+        ended: false,
+    }));
+    (RecordingInputTimes { input_times, state: state.clone() },
+     RecordedInputTimes { state, peeked: None })
+}
+
+pub struct RecordingInputTimes<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> {
+    input_times: I,
+    state: Rc<RefCell<RecordedState<T>>>,
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> Iterator for RecordingInputTimes<T, I> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        let next = self.input_times.next();
+        let mut state = self.state.borrow_mut();
+        match next {
+            Some(t) => state.issued.push_back(t),
+            None => state.ended = true,
+        }
+        next
+    }
+}
+
+impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> InputTimeResumableIterator<T> for RecordingInputTimes<T, I> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        self.input_times.peek()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        let end = self.input_times.end();
+        if end {
+            self.state.borrow_mut().ended = true;
+        }
+        end
+    }
+}
+
+pub struct RecordedInputTimes<T> {
+    state: Rc<RefCell<RecordedState<T>>>,
+    peeked: Option<T>,
+}
+
+impl<T: Copy+Eq+Ord> Iterator for RecordedInputTimes<T> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.state.borrow_mut().issued.pop_front()
+    }
+}
+
+impl<T: Copy+Eq+Ord> InputTimeResumableIterator<T> for RecordedInputTimes<T> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        self.peeked = self.state.borrow().issued.front().cloned();
+        self.peeked.as_ref()
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        let state = self.state.borrow();
+        state.ended && state.issued.is_empty()
+    }
+}
+
+struct ClosedLoopState<T> {
+    ready: VecDeque<T>,
+    outstanding: VecDeque<T>,
+    end: T,
+}
+
+/// Creates the two ends of a closed-loop arrival process that keeps `outstanding` inputs in
+/// flight: each acknowledgement lets the source issue a new input at the acknowledgement time.
+///
+/// The first end schedules the inputs (e.g. in a `SyntheticInputTimeGenerator`), the second end
+/// goes in the `MetricCollector` that acknowledges them; both must be in the same thread.
+/// No inputs are issued at or after `end`.
+pub fn closed_loop<T: Copy+Eq+Ord>(start: T, outstanding: usize, end: T) -> (ClosedLoopInputTimes<T>, ClosedLoopAcknowledgements<T>) {
+    assert!(outstanding > 0, "outstanding must be positive");
+    let state = Rc::new(RefCell::new(ClosedLoopState {
+        ready: (0..outstanding).map(|_| start).collect(),
+        outstanding: VecDeque::with_capacity(outstanding),
+        end,
+    }));
+    (ClosedLoopInputTimes { state: state.clone(), peeked: None },
+     ClosedLoopAcknowledgements { state, peeked: None })
+}
+
+pub struct ClosedLoopInputTimes<T> {
+    state: Rc<RefCell<ClosedLoopState<T>>>,
+    peeked: Option<T>,
+}
+
+impl<T: Copy+Eq+Ord> Iterator for ClosedLoopInputTimes<T> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        let mut state = self.state.borrow_mut();
+        let ClosedLoopState { ref mut ready, ref mut outstanding, end } = *state;
+        match ready.front() {
+            Some(&t) if t < end => {
+                ready.pop_front();
+                outstanding.push_back(t);
+                Some(t)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<T: Copy+Eq+Ord> InputTimeResumableIterator<T> for ClosedLoopInputTimes<T> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        let state = self.state.borrow();
+        self.peeked = state.ready.front().cloned().filter(|t| *t < state.end);
+        self.peeked.as_ref()
+    }
+    #[inline(always)]
+    #[allow(unknown_lints, clippy::unnecessary_map_or)] // `is_some_and` needs Rust 1.70
+    fn end(&self) -> bool {
+        let state = self.state.borrow();
+        state.ready.front().map_or(false, |t| *t >= state.end)
+    }
+}
+
+pub struct ClosedLoopAcknowledgements<T> {
+    state: Rc<RefCell<ClosedLoopState<T>>>,
+    peeked: Option<T>,
+}
+
+impl<T: Copy+Eq+Ord> Iterator for ClosedLoopAcknowledgements<T> {
+    type Item = T;
+    #[inline(always)]
+    fn next(&mut self) -> Option<T> {
+        self.state.borrow_mut().outstanding.pop_front()
+    }
+}
+
+impl<T: Copy+Eq+Ord> InputTimeResumableIterator<T> for ClosedLoopAcknowledgements<T> {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&T> {
+        self.peeked = self.state.borrow().outstanding.front().cloned();
+        self.peeked.as_ref()
+    }
+    #[inline(always)]
+    #[allow(unknown_lints, clippy::unnecessary_map_or)] // `is_some_and` needs Rust 1.70
+    fn end(&self) -> bool {
+        let state = self.state.borrow();
+        state.outstanding.is_empty() && state.ready.front().map_or(false, |t| *t >= state.end)
+    }
+    #[inline(always)]
+    fn acknowledged(&mut self, _input_t: T, at: T) {
+        self.state.borrow_mut().ready.push_back(at);
+    }
+}
+
+/// Arrivals (in ns) at an exact rate of `records` every `period` ns, without rounding the
+/// inter-arrival time: arrival `i` is at `first + floor(i * period / records)`.
+pub struct ExactRateInputTimes {
+    first: u64,
+    records: u64,
+    period: u64,
+    index: u64,
+    next: u64,
+    end: u64,
+}
+
+impl ExactRateInputTimes {
+    pub fn new(first: u64, records: u64, period: u64, end: u64) -> Self {
+        assert!(records > 0, "records must be positive");
+        Self {
+            first,
+            records,
+            period,
+            index: 0,
+            next: first,
+            end,
+        }
+    }
+
+    pub fn per_second(first: u64, records_per_second: u64, end: u64) -> Self {
+        Self::new(first, records_per_second, 1_000_000_000, end)
+    }
+}
+
+impl Iterator for ExactRateInputTimes {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        if !self.end() {
+            let n = self.next;
+            self.index += 1;
+            self.next = self.first + (self.index as u128 * self.period as u128 / self.records as u128) as u64;
+            Some(n)
+        } else {
+            None
+        }
+    }
+}
+
+impl InputTimeResumableIterator<u64> for ExactRateInputTimes {
+    #[inline(always)]
+    fn peek(&mut self) -> Option<&u64> {
+        if !self.end() {
+            Some(&self.next)
+        } else {
+            None
+        }
+    }
+    #[inline(always)]
+    fn end(&self) -> bool {
+        self.next >= self.end
+    }
+}
+
 pub struct SyntheticInputTimeGenerator<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> {
     input_times: I,
+    injected: u64,
     _phantom_data: ::std::marker::PhantomData<T>,
 }
 
@@ -62,10 +929,21 @@ impl<T: Copy+Eq+Ord, I: InputTimeResumableIterator<T>> SyntheticInputTimeGenerat
     pub fn new(input_times: I) -> Self {
         Self {
             input_times,
+            injected: 0,
             _phantom_data: ::std::marker::PhantomData,
         }
     }
 
+    /// Input times returned by `iter_until` so far.
+    pub fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// The input time the next record is scheduled at, `None` once the schedule has ended.
+    pub fn next_scheduled(&mut self) -> Option<T> {
+        self.input_times.peek().cloned()
+    }
+
     pub fn iter_until<'a>(&'a mut self, until: T) -> Option<impl Iterator<Item=T>+'a> {
         if self.input_times.end() {
             None
@@ -92,6 +970,7 @@ impl<'a, T: Copy+Eq+Ord+'a, I: InputTimeResumableIterator<T>+'a> Iterator for Sy
         } else if let Some(&next_t) = self.referenced.input_times.peek() {
             if next_t < self.until {
                 self.referenced.input_times.next().unwrap();
+                self.referenced.injected += 1;
                 Some(next_t)
             } else {
                 None
@@ -101,3 +980,81 @@ impl<'a, T: Copy+Eq+Ord+'a, I: InputTimeResumableIterator<T>+'a> Iterator for Sy
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_rate_per_second() {
+        let mut per_second = [0; 5];
+        for t in ExactRateInputTimes::per_second(0, 7, 5_000_000_000) {
+            per_second[(t / 1_000_000_000) as usize] += 1;
+        }
+        assert_eq!(per_second, [7; 5]);
+    }
+
+    fn arrivals_per_second(input_times: impl Iterator<Item=u64>, seconds: usize) -> Vec<usize> {
+        let mut per_second = vec![0; seconds];
+        for t in input_times {
+            per_second[(t / 1_000_000_000) as usize] += 1;
+        }
+        per_second
+    }
+
+    #[test]
+    fn variable_rate_follows_a_constant_curve() {
+        let per_second = arrivals_per_second(VariableRateInputTimes::new(0, |_| 1_000f64, 10_000_000_000), 10);
+        assert!(per_second.iter().all(|count| (*count as i64 - 1_000).abs() <= 1), "{:?}", per_second);
+    }
+
+    #[test]
+    fn variable_rate_follows_a_sinusoidal_curve() {
+        let rate = sinusoidal_rate(1_000f64, 500f64, 2_000_000_000);
+        let per_second = arrivals_per_second(VariableRateInputTimes::new(0, rate, 10_000_000_000), 10);
+        // 1000 +- 500 * 2 / pi in the rising and falling half of each period
+        let swing = 1_000f64 / ::std::f64::consts::PI;
+        for (second, count) in per_second.iter().enumerate() {
+            let expected = if second % 2 == 0 { 1_000f64 + swing } else { 1_000f64 - swing };
+            assert!((*count as f64 - expected).abs() <= 2f64, "second {}: {} vs {}", second, count, expected);
+        }
+    }
+
+    #[test]
+    fn partitions_add_up_to_the_global_schedule() {
+        let shares = [1, 2, 3];
+        let mut all = Vec::new();
+        for index in 0..shares.len() {
+            let partition = PartitionedInputTimes::with_shares(ConstantThroughputInputTimes::<u64, u64>::new(0, 1, 60), index, &shares);
+            let times = partition.collect::<Vec<_>>();
+            assert_eq!(times.len(), 10 * shares[index]);
+            all.extend(times);
+        }
+        all.sort();
+        assert_eq!(all, (0..60).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn offset_forwards_acknowledgements() {
+        let (source, acks) = closed_loop(0u64, 1, 100);
+        let mut source = OffsetInputTimes::new(source, 10);
+        let mut acks = OffsetInputTimes::new(acks, 10);
+        assert_eq!(source.next(), Some(10));
+        assert_eq!(acks.next(), Some(10));
+        acks.acknowledged(10, 15);
+        assert_eq!(source.peek(), Some(&15));
+    }
+
+    #[test]
+    fn merged_forwards_acknowledgements_to_the_arrival_side() {
+        let (mut source, acks) = closed_loop(0u64, 1, 100);
+        let mut merged = MergedInputTimes::new(ConstantThroughputInputTimes::new(1u64, 10, 100), acks);
+        assert_eq!(source.next(), Some(0));
+        assert_eq!(merged.next(), Some(0));
+        merged.acknowledged(0, 7);
+        assert_eq!(source.peek(), Some(&7));
+        assert_eq!(merged.next(), Some(1));
+        merged.acknowledged(1, 8);
+        assert_eq!(source.peek(), Some(&7));
+    }
+}
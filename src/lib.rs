@@ -1,17 +1,31 @@
-extern crate num_traits;
-
 #[cfg(feature = "hdrhist-support")]
 extern crate hdrhist;
 
 #[cfg(feature = "timely")]
 extern crate timely;
 
+#[cfg(feature = "rand-support")]
+extern crate rand;
+
 pub mod input;
 pub mod output;
 pub mod util;
 pub mod timeline;
 pub mod format;
+pub mod payload;
+pub mod clock;
+pub mod load;
+pub mod overload;
+pub mod window;
+pub mod histogram;
+pub mod capture;
+pub mod plot;
+pub mod report;
+pub mod adaptive;
 
 #[cfg(feature = "timely-support")]
 pub mod timely_support;
 
+#[cfg(feature = "prometheus-support")]
+pub mod prometheus;
+
@@ -0,0 +1,135 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use clock::{Clock, MonotonicClock};
+
+/// Tracks the data loading (setup) phase that precedes the measured part of an experiment.
+///
+/// Meant to be shared (e.g. via `Rc`) between the source that loads the data and the operators
+/// that acknowledge outputs: the measurement clock starts at zero when loading finishes.
+#[derive(Debug)]
+pub struct LoadPhase<C: Clock = MonotonicClock> {
+    clock: C,
+    started: Cell<Option<u64>>,
+    finished: Cell<Option<u64>>,
+    records: Cell<usize>,
+}
+
+impl LoadPhase<MonotonicClock> {
+    pub fn new() -> Self {
+        Self::with_clock(MonotonicClock::new())
+    }
+}
+
+impl Default for LoadPhase<MonotonicClock> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Clock> LoadPhase<C> {
+    pub fn with_clock(clock: C) -> Self {
+        Self {
+            clock,
+            started: Cell::new(None),
+            finished: Cell::new(None),
+            records: Cell::new(0),
+        }
+    }
+
+    pub fn start(&self) {
+        if self.started.get().is_none() {
+            self.started.set(Some(self.clock.now_ns()));
+        }
+    }
+
+    pub fn record_loaded(&self, records: usize) {
+        self.start();
+        self.records.set(self.records.get() + records);
+    }
+
+    pub fn finish(&self) {
+        if self.finished.get().is_none() {
+            let now = self.clock.now_ns();
+            if self.started.get().is_none() {
+                self.started.set(Some(now));
+            }
+            self.finished.set(Some(now));
+        }
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.finished.get().is_some()
+    }
+
+    /// Nanoseconds elapsed since loading finished, `None` while still loading.
+    #[inline(always)]
+    pub fn elapsed_ns(&self) -> Option<u64> {
+        self.finished.get().map(|t| self.clock.now_ns() - t)
+    }
+
+    /// Time elapsed since loading finished, `None` while still loading.
+    #[inline(always)]
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.elapsed_ns().map(Duration::from_nanos)
+    }
+
+    pub fn summary(&self) -> Option<LoadSummary> {
+        match (self.started.get(), self.finished.get()) {
+            (Some(started), Some(finished)) => Some(LoadSummary {
+                duration_ns: finished - started,
+                records: self.records.get(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadSummary {
+    pub duration_ns: u64,
+    pub records: usize,
+}
+
+impl LoadSummary {
+    /// Loaded records per second, `None` if loading took no measurable time.
+    pub fn throughput(&self) -> Option<f64> {
+        if self.duration_ns == 0 {
+            None
+        } else {
+            Some(self.records as f64 * 1_000_000_000f64 / self.duration_ns as f64)
+        }
+    }
+
+    /// Combines summaries from workers loading in parallel: the slowest worker determines the
+    /// duration, records are summed.
+    pub fn combined(self, other: Self) -> Self {
+        LoadSummary {
+            duration_ns: ::std::cmp::max(self.duration_ns, other.duration_ns),
+            records: self.records + other.records,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::VirtualClock;
+
+    #[test]
+    fn load_phase_on_a_virtual_clock() {
+        let clock = VirtualClock::new();
+        let phase = LoadPhase::with_clock(clock.clone());
+        clock.set(1_000);
+        phase.record_loaded(300);
+        clock.advance(2_000_000_000);
+        phase.record_loaded(300);
+        assert_eq!(phase.elapsed_ns(), None);
+        phase.finish();
+        clock.advance(5);
+        assert_eq!(phase.elapsed_ns(), Some(5));
+        let summary = phase.summary().unwrap();
+        assert_eq!(summary, LoadSummary { duration_ns: 2_000_000_000, records: 600 });
+        assert_eq!(summary.throughput(), Some(300f64));
+    }
+}
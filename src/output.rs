@@ -1,11 +1,24 @@
 use super::input::InputTimeResumableIterator;
-use num_traits::{Zero, Bounded};
+#[cfg(feature = "hdrhist-support")]
+use super::util::ToNanos;
 
 pub trait Metrics<T: Eq+Ord+Copy> {
-    #[inline(always)]
     fn record(&mut self, begin_t: T, end_t: T);
 
     fn combined(self, other: Self) -> Self;
+
+    /// Lets time-driven metrics make progress even when nothing is recorded, e.g. so that a
+    /// `Timeline` reports its intervals while the system under test is stalled: no sample
+    /// recorded from now on has an `end_t` before `now`.
+    fn flush_until(&mut self, _now: T) { }
+
+    /// Like `flush_until`, but no sample recorded from now on has a `begin_t` before `input_t`.
+    fn flush_inputs_until(&mut self, _input_t: T) { }
+
+    /// Like `combined`, but reports metrics that were configured differently instead of panicking.
+    fn try_combined(self, other: Self) -> Result<Self, CombineError> where Self: Sized {
+        Ok(self.combined(other))
+    }
 }
 
 #[cfg(feature = "hdrhist-support")]
@@ -19,9 +32,120 @@ impl Metrics<u64> for ::hdrhist::HDRHist {
     }
 }
 
+#[cfg(feature = "hdrhist-support")]
+impl Metrics<::std::time::Duration> for ::hdrhist::HDRHist {
+    fn record(&mut self, begin_t: ::std::time::Duration, end_t: ::std::time::Duration) {
+        self.add_value((end_t - begin_t).to_nanos());
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.combined(other)
+    }
+}
+
+#[cfg(feature = "hdrhist-support")]
+impl Metrics<::std::time::Instant> for ::hdrhist::HDRHist {
+    fn record(&mut self, begin_t: ::std::time::Instant, end_t: ::std::time::Instant) {
+        self.add_value(end_t.duration_since(begin_t).to_nanos());
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.combined(other)
+    }
+}
+
+/// Metrics that were configured differently and cannot be combined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CombineError {
+    Mismatch {
+        what: &'static str,
+        left: String,
+        right: String,
+    },
+    /// There was nothing to combine.
+    Empty,
+    /// Combining the input at `index` (counting from zero) with the preceding ones failed.
+    Input {
+        index: usize,
+        error: Box<CombineError>,
+    },
+    /// Combining timelines would leave elements between `start` and `end` that neither has a
+    /// metrics factory to create, see `Timeline::try_combined_with`.
+    Gap {
+        start: String,
+        end: String,
+    },
+    /// Reading or writing recorded data failed.
+    Io {
+        kind: ::std::io::ErrorKind,
+        message: String,
+    },
+}
+
+impl CombineError {
+    pub fn mismatch<V: ::std::fmt::Debug>(what: &'static str, left: V, right: V) -> Self {
+        CombineError::Mismatch {
+            what,
+            left: format!("{:?}", left),
+            right: format!("{:?}", right),
+        }
+    }
+}
+
+impl ::std::fmt::Display for CombineError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            CombineError::Mismatch { what, ref left, ref right } =>
+                write!(f, "mismatched {}: {} != {}", what, left, right),
+            CombineError::Empty => write!(f, "expected at least one metric"),
+            CombineError::Input { index, ref error } => write!(f, "input {}: {}", index, error),
+            CombineError::Gap { ref start, ref end } =>
+                write!(f, "no metrics factory to fill the gap from {} to {}", start, end),
+            CombineError::Io { ref message, .. } => write!(f, "I/O error: {}", message),
+        }
+    }
+}
+
+impl ::std::error::Error for CombineError { }
+
+impl From<::std::io::Error> for CombineError {
+    fn from(error: ::std::io::Error) -> Self {
+        CombineError::Io {
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl<T: Eq+Ord+Copy, A: Metrics<T>, B: Metrics<T>> Metrics<T> for (A, B) {
+    #[inline(always)]
+    fn record(&mut self, begin_t: T, end_t: T) {
+        self.0.record(begin_t, end_t);
+        self.1.record(begin_t, end_t);
+    }
+
+    fn flush_until(&mut self, now: T) {
+        self.0.flush_until(now);
+        self.1.flush_until(now);
+    }
+
+    fn flush_inputs_until(&mut self, input_t: T) {
+        self.0.flush_inputs_until(input_t);
+        self.1.flush_inputs_until(input_t);
+    }
+
+    fn combined(self, other: Self) -> Self {
+        (self.0.combined(other.0), self.1.combined(other.1))
+    }
+
+    fn try_combined(self, other: Self) -> Result<Self, CombineError> {
+        Ok((self.0.try_combined(other.0)?, self.1.try_combined(other.1)?))
+    }
+}
+
 #[derive(Debug)]
 pub struct MetricCollector<
-    T: Eq+Ord+Copy+Zero+Bounded,
+    T: Eq+Ord+Copy,
     I: InputTimeResumableIterator<T>,
     M: Metrics<T>> {
 
@@ -32,7 +156,7 @@ pub struct MetricCollector<
 }
 
 impl<
-    T: Eq+Ord+Copy+Zero+Bounded,
+    T: Eq+Ord+Copy,
     I: InputTimeResumableIterator<T>,
     M: Metrics<T>> MetricCollector<T, I, M> {
 
@@ -50,14 +174,55 @@ impl<
         latency_metrics
     }
 
+    pub fn metrics(&self) -> &M {
+        &self.latency_metrics
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut M {
+        &mut self.latency_metrics
+    }
+
     pub fn recorded_samples(&self) -> usize {
         self.recorded_samples
     }
 
+    /// Lets the metrics make progress up to `now` even if nothing was acknowledged, see
+    /// `Metrics::flush_until`. Metrics by input time only make progress up to the earliest
+    /// unacknowledged input, which may be well before `now` when latency is high.
+    pub fn flush_until(&mut self, now: T) {
+        self.latency_metrics.flush_until(now);
+        match self.input_times.peek().cloned() {
+            Some(input_t) => self.latency_metrics.flush_inputs_until(input_t),
+            None if self.input_times.end() => self.latency_metrics.flush_inputs_until(now),
+            // inputs that were not issued yet may still be scheduled before `now`
+            None => (),
+        }
+    }
+
+    /// The earliest input time that has not been acknowledged yet.
+    pub fn next_unacknowledged(&mut self) -> Option<T> {
+        self.input_times.peek().cloned()
+    }
+
+    /// Skips the inputs scheduled before `until` without recording them, e.g. at the end of a
+    /// run, and returns how many there were.
+    pub fn drain_unacknowledged(&mut self, until: T) -> usize {
+        let mut drained = 0;
+        while let Some(&input_t) = self.input_times.peek() {
+            if input_t >= until {
+                break;
+            }
+            self.input_times.next().unwrap();
+            drained += 1;
+        }
+        drained
+    }
+
     #[inline(always)]
     pub fn acknowledge_next(&mut self, at: T) {
         let begin_t = self.input_times.next().expect("No additional input_times");
         self.latency_metrics.record(begin_t, at);
+        self.input_times.acknowledged(begin_t, at);
         self.recorded_samples += 1;
     }
 
@@ -68,6 +233,7 @@ impl<
                 if input_t <= till_input_t {
                     self.input_times.next().unwrap();
                     self.latency_metrics.record(input_t, at);
+                    self.input_times.acknowledged(input_t, at);
                     self.recorded_samples += 1;
                     continue;
                 }
@@ -83,6 +249,7 @@ impl<
                 if ack(input_t) {
                     self.input_times.next().unwrap();
                     self.latency_metrics.record(input_t, at);
+                    self.input_times.acknowledged(input_t, at);
                     self.recorded_samples += 1;
                     continue;
                 }
@@ -92,6 +259,13 @@ impl<
     }
 }
 
+impl<I: InputTimeResumableIterator<u64>, M: Metrics<u64>> MetricCollector<u64, I, M> {
+    /// Age at `now` of the oldest input that is due but not acknowledged, 0 if there is none.
+    pub fn backlog(&mut self, now: u64) -> u64 {
+        self.next_unacknowledged().map_or(0, |input_t| now.saturating_sub(input_t))
+    }
+}
+
 pub struct WarmupDurationMetrics<T: Eq+Ord+Copy, M: Metrics<T>> {
     metrics: M,
     warmup_end: T,
@@ -107,6 +281,10 @@ impl<T: Eq+Ord+Copy+::std::fmt::Debug, M: Metrics<T>> WarmupDurationMetrics<T, M
         }
     }
 
+    pub fn metrics(&self) -> &M {
+        &self.metrics
+    }
+
     pub fn into_inner(self) -> M {
         let WarmupDurationMetrics {
             metrics,
@@ -123,7 +301,19 @@ impl<T: Eq+Ord+Copy+::std::fmt::Debug, M: Metrics<T>> Metrics<T> for WarmupDurat
         }
     }
 
+    fn flush_until(&mut self, now: T) {
+        self.metrics.flush_until(now);
+    }
+
+    fn flush_inputs_until(&mut self, input_t: T) {
+        self.metrics.flush_inputs_until(input_t);
+    }
+
     fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_combined(self, other: Self) -> Result<Self, CombineError> {
         let WarmupDurationMetrics {
             metrics,
             warmup_end,
@@ -134,13 +324,106 @@ impl<T: Eq+Ord+Copy+::std::fmt::Debug, M: Metrics<T>> Metrics<T> for WarmupDurat
             warmup_end: other_warmup_end,
             experiment_end: other_experiment_end,
         } = other;
-        assert_eq!(warmup_end, other_warmup_end);
-        assert_eq!(experiment_end, other_experiment_end);
-        WarmupDurationMetrics {
-            metrics: metrics.combined(other_metrics),
+        if warmup_end != other_warmup_end {
+            return Err(CombineError::mismatch("warmup_end", warmup_end, other_warmup_end));
+        }
+        if experiment_end != other_experiment_end {
+            return Err(CombineError::mismatch("experiment_end", experiment_end, other_experiment_end));
+        }
+        Ok(WarmupDurationMetrics {
+            metrics: metrics.try_combined(other_metrics)?,
             warmup_end,
             experiment_end,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Phase<T: Eq+Ord+Copy, M: Metrics<T>> {
+    pub name: String,
+    pub start: T,
+    pub end: T,
+    pub metrics: M,
+    pub samples: usize,
+}
+
+/// Keeps separate metrics for each of several named phases of an experiment (e.g. warmup, steady
+/// state, fault injection, recovery), each covering inputs with `begin_t` in `[start, end)`.
+///
+/// Phases may overlap, in which case a sample is recorded in all of them.
+pub struct PhasedMetrics<T: Eq+Ord+Copy, M: Metrics<T>> {
+    phases: Vec<Phase<T, M>>,
+}
+
+impl<T: Eq+Ord+Copy+::std::fmt::Debug, M: Metrics<T>> PhasedMetrics<T, M> {
+    pub fn new<S: Into<String>>(phases: impl IntoIterator<Item=(S, T, T)>, metrics: impl Fn()->M) -> Self {
+        Self {
+            phases: phases.into_iter().map(|(name, start, end)| Phase {
+                name: name.into(),
+                start,
+                end,
+                metrics: metrics(),
+                samples: 0,
+            }).collect(),
+        }
+    }
+
+    pub fn phases(&self) -> &[Phase<T, M>] {
+        &self.phases
+    }
+
+    pub fn into_phases(self) -> Vec<Phase<T, M>> {
+        self.phases
+    }
+}
+
+impl<T: Eq+Ord+Copy+::std::fmt::Debug, M: Metrics<T>> Metrics<T> for PhasedMetrics<T, M> {
+    #[inline(always)]
+    fn record(&mut self, begin_t: T, end_t: T) {
+        for phase in self.phases.iter_mut() {
+            if begin_t >= phase.start && begin_t < phase.end {
+                phase.metrics.record(begin_t, end_t);
+                phase.samples += 1;
+            }
+        }
+    }
+
+    fn flush_until(&mut self, now: T) {
+        for phase in self.phases.iter_mut() {
+            phase.metrics.flush_until(now);
+        }
+    }
+
+    fn flush_inputs_until(&mut self, input_t: T) {
+        for phase in self.phases.iter_mut() {
+            phase.metrics.flush_inputs_until(input_t);
+        }
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_combined(self, other: Self) -> Result<Self, CombineError> {
+        if self.phases.len() != other.phases.len() {
+            return Err(CombineError::mismatch("number of phases", self.phases.len(), other.phases.len()));
+        }
+        let mut phases = Vec::with_capacity(self.phases.len());
+        for (phase, other_phase) in self.phases.into_iter().zip(other.phases) {
+            if phase.name != other_phase.name {
+                return Err(CombineError::mismatch("phase name", phase.name, other_phase.name));
+            }
+            if phase.start != other_phase.start || phase.end != other_phase.end {
+                return Err(CombineError::mismatch("phase bounds",
+                    (phase.start, phase.end), (other_phase.start, other_phase.end)));
+            }
+            phases.push(Phase {
+                metrics: phase.metrics.try_combined(other_phase.metrics)?,
+                samples: phase.samples + other_phase.samples,
+                ..phase
+            });
         }
+        Ok(PhasedMetrics { phases })
     }
 }
 
@@ -150,25 +433,54 @@ pub fn combine_all<T: Eq+Ord+Copy, M: Metrics<T>, A: IntoIterator<Item=M>>(all:
     it.fold(first, |a, b| a.combined(b))
 }
 
+/// Like `combine_all`, but reports which input could not be combined instead of panicking.
+pub fn try_combine_all<T: Eq+Ord+Copy, M: Metrics<T>, A: IntoIterator<Item=M>>(all: A) -> Result<M, CombineError> {
+    let mut it = all.into_iter();
+    let first = it.next().ok_or(CombineError::Empty)?;
+    it.enumerate().try_fold(first, |a, (i, b)| a.try_combined(b).map_err(|error| CombineError::Input {
+        index: i + 1,
+        error: Box::new(error),
+    }))
+}
+
 pub mod default {
+    pub type DistributionTimeline<M> =
+        ::timeline::Timeline<u64, u64, super::WarmupDurationMetrics<u64, M>, M>;
+
     #[cfg(feature = "hdrhist-support")]
-    pub fn hdrhist_timeline_collector<I: super::InputTimeResumableIterator<u64>>(
+    pub type HDRHistTimeline = DistributionTimeline<::hdrhist::HDRHist>;
+
+    /// Like `hdrhist_timeline_collector`, with metrics built by `metrics` (e.g. one of the
+    /// `histogram` backends).
+    pub fn timeline_collector<I: super::InputTimeResumableIterator<u64>, M: super::Metrics<u64>>(
         input_times: I,
         start: u64,
         overall_start: u64,
         overall_end: u64,
         total_duration: u64,
-        timeline_interval: u64) ->
-        super::MetricCollector<
-            u64,
-            I,
-            ::timeline::Timeline<u64, u64, super::WarmupDurationMetrics<u64, ::hdrhist::HDRHist>, ::hdrhist::HDRHist>> {
+        timeline_interval: u64,
+        metrics: impl Fn()->M) ->
+        super::MetricCollector<u64, I, DistributionTimeline<M>> {
 
         super::MetricCollector::new(
             input_times,
             ::timeline::Timeline::new(
                 start, total_duration, timeline_interval,
-                super::WarmupDurationMetrics::new(::hdrhist::HDRHist::new(), overall_start, overall_end),
-                || ::hdrhist::HDRHist::new()))
+                super::WarmupDurationMetrics::new(metrics(), overall_start, overall_end),
+                metrics))
+    }
+
+    #[cfg(feature = "hdrhist-support")]
+    pub fn hdrhist_timeline_collector<I: super::InputTimeResumableIterator<u64>>(
+        input_times: I,
+        start: u64,
+        overall_start: u64,
+        overall_end: u64,
+        total_duration: u64,
+        timeline_interval: u64) ->
+        super::MetricCollector<u64, I, HDRHistTimeline> {
+
+        timeline_collector(input_times, start, overall_start, overall_end, total_duration, timeline_interval,
+            ::hdrhist::HDRHist::new)
     }
 }
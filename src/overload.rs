@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use histogram::{Distribution, interval_quantile};
+use input::InputTimeResumableIterator;
+use output::{Metrics, MetricCollector};
+use timeline::TimelineElement;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overload {
+    /// Latency trended upwards over the last `intervals` intervals, by `growth` overall.
+    LatencyDiverging { intervals: usize, growth: f64 },
+    /// Unacknowledged inputs exceeded the configured maximum.
+    Backlog { backlog: u64 },
+}
+
+/// Shared flag that is set once overload is detected, so that the driver can stop the run.
+#[derive(Debug, Clone, Default)]
+pub struct OverloadSignal(Arc<AtomicBool>);
+
+impl OverloadSignal {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+/// Detects that the system under test cannot keep up with the offered load.
+///
+/// Feed it per-interval latencies (e.g. from a `Timeline` reporter) and/or the current backlog
+/// of unacknowledged inputs; the first detected `Overload` is kept and the signal is set.
+pub struct OverloadDetector {
+    window: usize,
+    growth_factor: f64,
+    max_backlog: u64,
+    latencies: VecDeque<u64>,
+    overload: Option<Overload>,
+    signal: OverloadSignal,
+}
+
+impl OverloadDetector {
+    /// Overload is detected when the least-squares slope of the latency over the last `window`
+    /// intervals is positive and latency grew by at least `growth_factor` overall, or when the
+    /// backlog exceeds `max_backlog`.
+    ///
+    /// The slope tolerates the plateaus and noise of a real run, which interrupt strictly
+    /// increasing latencies.
+    pub fn new(window: usize, growth_factor: f64, max_backlog: u64) -> Self {
+        assert!(window > 0, "window must be positive");
+        Self {
+            window,
+            growth_factor,
+            max_backlog,
+            latencies: VecDeque::with_capacity(window + 1),
+            overload: None,
+            signal: OverloadSignal::default(),
+        }
+    }
+
+    pub fn signal(&self) -> OverloadSignal {
+        self.signal.clone()
+    }
+
+    pub fn overload(&self) -> Option<Overload> {
+        self.overload
+    }
+
+    pub fn observe_interval(&mut self, latency: u64) -> Option<Overload> {
+        self.latencies.push_back(latency);
+        if self.latencies.len() > self.window + 1 {
+            self.latencies.pop_front();
+        }
+        if self.latencies.len() == self.window + 1 {
+            let increasing = slope(&self.latencies) > 0f64;
+            let first = self.latencies[0];
+            let last = self.latencies[self.window];
+            let growth = if first == 0 { f64::INFINITY } else { last as f64 / first as f64 };
+            if increasing && growth >= self.growth_factor {
+                self.detected(Overload::LatencyDiverging { intervals: self.window, growth });
+            }
+        }
+        self.overload
+    }
+
+    /// `backlog` can be in any unit, as long as it matches `max_backlog`: e.g. the number of
+    /// scheduled but unacknowledged inputs, or the age of the oldest one.
+    pub fn observe_backlog(&mut self, backlog: u64) -> Option<Overload> {
+        if backlog > self.max_backlog {
+            self.detected(Overload::Backlog { backlog });
+        }
+        self.overload
+    }
+
+    /// Observes the `backlog` of `collector` at `now`, so `max_backlog` is an age in ns.
+    pub fn observe_collector<I: InputTimeResumableIterator<u64>, M: Metrics<u64>>(
+        &mut self,
+        collector: &mut MetricCollector<u64, I, M>,
+        now: u64) -> Option<Overload> {
+
+        let backlog = collector.backlog(now);
+        self.observe_backlog(backlog)
+    }
+
+    /// Uses the upper bound of the 99th percentile as the interval's latency; empty intervals
+    /// are ignored.
+    pub fn observe_element<M: Metrics<u64>+Distribution>(&mut self, element: &TimelineElement<u64, M>) -> Option<Overload> {
+        match interval_quantile(element, 0.99) {
+            Some(p99) => self.observe_interval(p99),
+            None => self.overload,
+        }
+    }
+
+    fn detected(&mut self, overload: Overload) {
+        if self.overload.is_none() {
+            self.overload = Some(overload);
+            self.signal.set();
+        }
+    }
+}
+
+// least-squares slope of the latencies against their index
+fn slope(latencies: &VecDeque<u64>) -> f64 {
+    let n = latencies.len() as f64;
+    let mean_x = (n - 1f64) / 2f64;
+    let mean_y = latencies.iter().map(|l| *l as f64).sum::<f64>() / n;
+    let (covariance, variance) = latencies.iter().enumerate().fold((0f64, 0f64), |(c, v), (x, y)| {
+        let dx = x as f64 - mean_x;
+        (c + dx * (*y as f64 - mean_y), v + dx * dx)
+    });
+    covariance / variance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diverging_latency_with_a_plateau() {
+        let mut detector = OverloadDetector::new(4, 2f64, u64::MAX);
+        for latency in [100, 150, 150, 140, 250].iter() {
+            detector.observe_interval(*latency);
+        }
+        assert_eq!(detector.overload(), Some(Overload::LatencyDiverging { intervals: 4, growth: 2.5 }));
+        assert!(detector.signal().is_set());
+    }
+
+    #[test]
+    fn steady_latency() {
+        let mut detector = OverloadDetector::new(4, 2f64, u64::MAX);
+        for latency in [100, 300, 100, 100, 100, 300, 100].iter() {
+            detector.observe_interval(*latency);
+        }
+        assert_eq!(detector.overload(), None);
+    }
+
+    #[test]
+    fn decayed_histogram_elements() {
+        let mut detector = OverloadDetector::new(2, 2f64, u64::MAX);
+        for (i, latency) in [100u64, 1_000, 10_000].iter().enumerate() {
+            let time = i as u64 * 1_000_000;
+            let mut metrics = ::window::DecayedHistogram::new(1_000_000);
+            metrics.record(time, time + latency);
+            detector.observe_element(&TimelineElement { time, metrics, samples: 1 });
+        }
+        assert!(detector.overload().is_some());
+    }
+
+    #[test]
+    fn backlog_of_a_stalled_collector() {
+        let input_times = ::input::ConstantThroughputInputTimes::<u64, u64>::new(0, 10, 100);
+        let mut collector = MetricCollector::new(input_times, ::histogram::SortedSamples::new());
+        collector.acknowledge_till_input_t(25, 20);
+        let mut detector = OverloadDetector::new(4, 2f64, 40);
+        assert_eq!(collector.backlog(5), 0);
+        assert_eq!(detector.observe_collector(&mut collector, 60), None);
+        assert_eq!(detector.observe_collector(&mut collector, 80), Some(Overload::Backlog { backlog: 50 }));
+    }
+}
@@ -0,0 +1,211 @@
+#[cfg(feature = "rand-support")]
+use rand::{Rng, SeedableRng};
+
+/// Builds a small, fast rng that is deterministic for a given `(seed, worker_index)` pair.
+#[cfg(feature = "rand-support")]
+pub fn seeded_rng(seed: u64, worker_index: usize) -> ::rand::rngs::SmallRng {
+    let mut bytes = [0u8; 16];
+    for i in 0..8 {
+        bytes[i] = (seed >> (8 * i)) as u8;
+        bytes[8 + i] = (worker_index as u64 >> (8 * i)) as u8;
+    }
+    // the underlying xorshift generator must not be seeded with all zeros
+    bytes[15] ^= 0x80;
+    ::rand::rngs::SmallRng::from_seed(bytes)
+}
+
+/// Cycles through `first`, `first + stride`, ... modulo `keys`.
+pub struct SequentialKeys {
+    next: u64,
+    stride: u64,
+    keys: u64,
+}
+
+impl SequentialKeys {
+    pub fn new(first: u64, stride: u64, keys: u64) -> Self {
+        assert!(keys > 0, "keys must be positive");
+        Self {
+            next: first % keys,
+            stride: stride % keys,
+            keys,
+        }
+    }
+}
+
+impl Iterator for SequentialKeys {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        let n = self.next;
+        self.next = (self.next + self.stride) % self.keys;
+        Some(n)
+    }
+}
+
+/// Draws keys uniformly from `[0, keys)`.
+#[cfg(feature = "rand-support")]
+pub struct UniformKeys<R: Rng> {
+    rng: R,
+    keys: u64,
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: Rng> UniformKeys<R> {
+    pub fn new(rng: R, keys: u64) -> Self {
+        assert!(keys > 0, "keys must be positive");
+        Self {
+            rng,
+            keys,
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: Rng> Iterator for UniformKeys<R> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        Some(self.rng.gen_range(0, self.keys))
+    }
+}
+
+/// Draws `hot_fraction` of the keys from `[0, hot_keys)` and the rest uniformly from `[hot_keys, keys)`.
+#[cfg(feature = "rand-support")]
+pub struct HotspotKeys<R: Rng> {
+    rng: R,
+    keys: u64,
+    hot_keys: u64,
+    hot_fraction: f64,
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: Rng> HotspotKeys<R> {
+    pub fn new(rng: R, keys: u64, hot_keys: u64, hot_fraction: f64) -> Self {
+        assert!(hot_keys > 0 && hot_keys <= keys, "hot_keys must be in (0, keys]");
+        assert!((0.0..=1.0).contains(&hot_fraction), "hot_fraction must be in [0, 1]");
+        Self {
+            rng,
+            keys,
+            hot_keys,
+            hot_fraction,
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: Rng> Iterator for HotspotKeys<R> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        if self.hot_keys == self.keys || self.rng.gen::<f64>() < self.hot_fraction {
+            Some(self.rng.gen_range(0, self.hot_keys))
+        } else {
+            Some(self.rng.gen_range(self.hot_keys, self.keys))
+        }
+    }
+}
+
+/// Draws keys from `[0, keys)` where key `k` has probability proportional to `1 / (k + 1)^exponent`.
+///
+/// Uses rejection-inversion sampling (Hörmann and Derflinger), which needs constant time and
+/// memory regardless of the number of keys.
+#[cfg(feature = "rand-support")]
+pub struct ZipfKeys<R: Rng> {
+    rng: R,
+    keys: u64,
+    exponent: f64,
+    h_integral_x1: f64,
+    h_integral_keys: f64,
+    s: f64,
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: Rng> ZipfKeys<R> {
+    pub fn new(rng: R, keys: u64, exponent: f64) -> Self {
+        assert!(keys > 0, "keys must be positive");
+        assert!(exponent > 0.0, "exponent must be positive");
+        let h_integral_x1 = h_integral(1.5, exponent) - 1.0;
+        let h_integral_keys = h_integral(keys as f64 + 0.5, exponent);
+        let s = 2.0 - h_integral_inverse(h_integral(2.5, exponent) - h(2.0, exponent), exponent);
+        Self {
+            rng,
+            keys,
+            exponent,
+            h_integral_x1,
+            h_integral_keys,
+            s,
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+impl<R: Rng> Iterator for ZipfKeys<R> {
+    type Item = u64;
+    #[inline(always)]
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            let u = self.h_integral_keys + self.rng.gen::<f64>() * (self.h_integral_x1 - self.h_integral_keys);
+            let x = h_integral_inverse(u, self.exponent);
+            let k = (x + 0.5).max(1.0).min(self.keys as f64) as u64;
+            if k as f64 - x <= self.s || u >= h_integral(k as f64 + 0.5, self.exponent) - h(k as f64, self.exponent) {
+                return Some(k - 1);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rand-support")]
+fn h(x: f64, exponent: f64) -> f64 {
+    (-exponent * x.ln()).exp()
+}
+
+#[cfg(feature = "rand-support")]
+fn h_integral(x: f64, exponent: f64) -> f64 {
+    let log_x = x.ln();
+    helper2((1.0 - exponent) * log_x) * log_x
+}
+
+#[cfg(feature = "rand-support")]
+fn h_integral_inverse(x: f64, exponent: f64) -> f64 {
+    let t = (x * (1.0 - exponent)).max(-1.0);
+    (helper1(t) * x).exp()
+}
+
+// ln(1 + x) / x, accurate near 0
+#[cfg(feature = "rand-support")]
+fn helper1(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.ln_1p() / x
+    } else {
+        1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x))
+    }
+}
+
+// (exp(x) - 1) / x, accurate near 0
+#[cfg(feature = "rand-support")]
+fn helper2(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.exp_m1() / x
+    } else {
+        1.0 + x * 0.5 * (1.0 + x * (1.0 / 3.0) * (1.0 + 0.25 * x))
+    }
+}
+
+#[cfg(all(test, feature = "rand-support"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zipf_frequencies_follow_the_exponent() {
+        let exponent = 1.2;
+        let samples = 200_000;
+        let mut counts = [0u64; 10];
+        for key in ZipfKeys::new(seeded_rng(1, 0), 10, exponent).take(samples) {
+            counts[key as usize] += 1;
+        }
+        for (key, count) in counts.iter().enumerate().take(5) {
+            let expected = counts[0] as f64 * (key as f64 + 1.0).powf(-exponent);
+            assert!((*count as f64 - expected).abs() < 0.05 * expected, "key {}: {} vs {}", key, count, expected);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt::Write;
+
+use histogram::Distribution;
+use output::Metrics;
+use timeline::TimelineElement;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 500.0;
+const MARGIN_LEFT: f64 = 80.0;
+const MARGIN_RIGHT: f64 = 150.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 60.0;
+const COLORS: [&str; 8] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    Linear,
+    /// Base 10; points with non-positive values are left out.
+    Log,
+}
+
+impl Scale {
+    fn transform(self, v: f64) -> f64 {
+        match self {
+            Scale::Linear => v,
+            Scale::Log => v.log10(),
+        }
+    }
+
+    fn includes(self, v: f64) -> bool {
+        v.is_finite() && (self == Scale::Linear || v > 0f64)
+    }
+
+    // tick values within the transformed range [min, max]
+    fn ticks(self, min: f64, max: f64) -> Vec<f64> {
+        match self {
+            Scale::Log => {
+                let ticks = (min.ceil() as i32..=max.floor() as i32).map(|e| 10f64.powi(e)).collect::<Vec<_>>();
+                // less than a decade: label the ends instead
+                if ticks.len() < 2 { vec![10f64.powf(min), 10f64.powf(max)] } else { ticks }
+            },
+            Scale::Linear => {
+                let rough = (max - min) / 5f64;
+                let magnitude = 10f64.powf(rough.log10().floor());
+                let step = [1f64, 2f64, 5f64, 10f64].iter().map(|m| m * magnitude).find(|s| *s >= rough).unwrap();
+                let first = (min / step).ceil() as i64;
+                let last = (max / step).floor() as i64;
+                (first..=last).map(|i| i as f64 * step).collect()
+            },
+        }
+    }
+}
+
+struct Series {
+    label: String,
+    points: Vec<(f64, f64)>,
+}
+
+/// A line chart, rendered as a standalone SVG document.
+pub struct Chart {
+    title: String,
+    x_label: String,
+    x_scale: Scale,
+    y_label: String,
+    y_scale: Scale,
+    markers: bool,
+    series: Vec<Series>,
+}
+
+impl Chart {
+    pub fn new(title: impl Into<String>, x_label: impl Into<String>, x_scale: Scale, y_label: impl Into<String>, y_scale: Scale) -> Self {
+        Self {
+            title: title.into(),
+            x_label: x_label.into(),
+            x_scale,
+            y_label: y_label.into(),
+            y_scale,
+            markers: false,
+            series: Vec::new(),
+        }
+    }
+
+    /// Also draws a marker at each point, e.g. for the runs of a sweep.
+    pub fn with_markers(mut self) -> Self {
+        self.markers = true;
+        self
+    }
+
+    pub fn add_series(&mut self, label: impl Into<String>, points: Vec<(f64, f64)>) {
+        let (x_scale, y_scale) = (self.x_scale, self.y_scale);
+        self.series.push(Series {
+            label: label.into(),
+            points: points.into_iter().filter(|&(x, y)| x_scale.includes(x) && y_scale.includes(y)).collect(),
+        });
+    }
+
+    pub fn to_svg(&self) -> String {
+        let (x_min, x_max) = self.range(|p| self.x_scale.transform(p.0));
+        let (y_min, y_max) = self.range(|p| self.y_scale.transform(p.1));
+        let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+        let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        let x = |v: f64| MARGIN_LEFT + (v - x_min) / (x_max - x_min) * plot_width;
+        let y = |v: f64| MARGIN_TOP + plot_height - (v - y_min) / (y_max - y_min) * plot_height;
+
+        let mut svg = String::new();
+        writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">",
+                 WIDTH, HEIGHT, WIDTH, HEIGHT).unwrap();
+        writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>").unwrap();
+        writeln!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"16\">{}</text>",
+                 MARGIN_LEFT + plot_width / 2f64, MARGIN_TOP / 2f64 + 6f64, escape(&self.title)).unwrap();
+
+        for tick in self.x_scale.ticks(x_min, x_max) {
+            let tx = x(self.x_scale.transform(tick));
+            writeln!(svg, "<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"#ddd\"/>", tx, MARGIN_TOP, tx, MARGIN_TOP + plot_height).unwrap();
+            writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>", tx, MARGIN_TOP + plot_height + 16f64, format_tick(tick)).unwrap();
+        }
+        for tick in self.y_scale.ticks(y_min, y_max) {
+            let ty = y(self.y_scale.transform(tick));
+            writeln!(svg, "<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#ddd\"/>", MARGIN_LEFT, ty, MARGIN_LEFT + plot_width, ty).unwrap();
+            writeln!(svg, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>", MARGIN_LEFT - 6f64, ty + 4f64, format_tick(tick)).unwrap();
+        }
+        writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>",
+                 MARGIN_LEFT, MARGIN_TOP, plot_width, plot_height).unwrap();
+        writeln!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                 MARGIN_LEFT + plot_width / 2f64, HEIGHT - 16f64, escape(&self.x_label)).unwrap();
+        writeln!(svg, "<text transform=\"translate(20 {}) rotate(-90)\" text-anchor=\"middle\">{}</text>",
+                 MARGIN_TOP + plot_height / 2f64, escape(&self.y_label)).unwrap();
+
+        for (i, series) in self.series.iter().enumerate() {
+            let color = COLORS[i % COLORS.len()];
+            let points = series.points.iter()
+                .map(|&(px, py)| (x(self.x_scale.transform(px)), y(self.y_scale.transform(py))))
+                .collect::<Vec<_>>();
+            writeln!(svg, "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>", color,
+                     points.iter().map(|(px, py)| format!("{:.1},{:.1}", px, py)).collect::<Vec<_>>().join(" ")).unwrap();
+            if self.markers {
+                for (px, py) in points.iter() {
+                    writeln!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"{}\"/>", px, py, color).unwrap();
+                }
+            }
+            let ly = MARGIN_TOP + 10f64 + 18f64 * i as f64;
+            writeln!(svg, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>",
+                     WIDTH - MARGIN_RIGHT + 10f64, ly, WIDTH - MARGIN_RIGHT + 30f64, ly, color).unwrap();
+            writeln!(svg, "<text x=\"{}\" y=\"{}\">{}</text>", WIDTH - MARGIN_RIGHT + 36f64, ly + 4f64, escape(&series.label)).unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    // transformed range of the points, never empty
+    fn range(&self, value: impl Fn(&(f64, f64)) -> f64) -> (f64, f64) {
+        let values = self.series.iter().flat_map(|s| s.points.iter()).map(value);
+        let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+        if min > max {
+            (0f64, 1f64)
+        } else if min == max {
+            (min - 1f64, max + 1f64)
+        } else {
+            (min, max)
+        }
+    }
+}
+
+/// Complementary cumulative distribution of each latency distribution, on log-log axes.
+pub fn ccdf_chart<M: Distribution>(title: impl Into<String>, distributions: &[(&str, &M)]) -> Chart {
+    let mut chart = Chart::new(title, "latency (ns)", Scale::Log, "fraction of samples", Scale::Log);
+    for (label, distribution) in distributions.iter() {
+        chart.add_series(*label, distribution.ccdf_upper_bounds().into_iter().map(|(v, f)| (v as f64, f)).collect());
+    }
+    chart
+}
+
+/// The `quantiles` of each interval of a timeline (times in ns); empty intervals are left out.
+pub fn percentile_timeline_chart<M: Metrics<u64>+Distribution>(
+    title: impl Into<String>,
+    timeline: &[TimelineElement<u64, M>],
+    quantiles: &[f64]) -> Chart {
+
+    let mut chart = Chart::new(title, "time (s)", Scale::Linear, "latency (ns)", Scale::Log);
+    let bounds = timeline.iter().filter(|e| e.samples > 0)
+        .map(|e| (e.time as f64 / 1_000_000_000f64, e.metrics.quantile_upper_bounds(quantiles)))
+        .collect::<Vec<_>>();
+    for (i, quantile) in quantiles.iter().enumerate() {
+        chart.add_series(format_quantile(*quantile), bounds.iter().map(|(t, b)| (*t, b[i] as f64)).collect());
+    }
+    chart
+}
+
+/// The `quantiles` of each run of a sweep, against the run's throughput (records/s).
+pub fn latency_throughput_chart<M: Distribution>(title: impl Into<String>, runs: &[(f64, &M)], quantiles: &[f64]) -> Chart {
+    let mut chart = Chart::new(title, "throughput (records/s)", Scale::Linear, "latency (ns)", Scale::Log).with_markers();
+    let mut runs = runs.iter().map(|(throughput, m)| (*throughput, m.quantile_upper_bounds(quantiles))).collect::<Vec<_>>();
+    runs.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("throughput is NaN"));
+    for (i, quantile) in quantiles.iter().enumerate() {
+        chart.add_series(format_quantile(*quantile), runs.iter().map(|(t, b)| (*t, b[i] as f64)).collect());
+    }
+    chart
+}
+
+fn format_quantile(quantile: f64) -> String {
+    if quantile >= 1f64 {
+        "max".to_string()
+    } else {
+        format!("p{}", trim_decimals(&format!("{:.4}", quantile * 100f64)))
+    }
+}
+
+fn format_tick(v: f64) -> String {
+    if v != 0f64 && (v.abs() >= 1e6 || v.abs() < 1e-3) {
+        format!("{:.3e}", v).replace(".000e", "e")
+    } else {
+        trim_decimals(&format!("{:.3}", v)).to_string()
+    }
+}
+
+fn trim_decimals(number: &str) -> &str {
+    number.trim_end_matches('0').trim_end_matches('.')
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hdrhist::HDRHist;
+
+use input::{InputTimeResumableIterator, SyntheticInputTimeGenerator};
+use output::{Metrics, MetricCollector};
+
+// `quantile` is reserved for summaries, which need a sum that `HDRHist` does not keep
+const EXPORTED_PERCENTILES: [(&str, f64); 5] = [("50", 0.5), ("90", 0.9), ("99", 0.99), ("99.9", 0.999), ("100", 1.0)];
+const STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A point-in-time view of a running experiment, published by each worker.
+#[derive(Clone)]
+pub struct BenchmarkMetrics {
+    pub latency: HDRHist,
+    pub recorded_samples: usize,
+    /// Records per second the input times are scheduled at.
+    pub offered_rate: f64,
+    /// Records per second that have been acknowledged so far.
+    pub achieved_rate: f64,
+    /// How far behind its schedule the source is injecting input.
+    pub injection_lag_ns: u64,
+}
+
+impl BenchmarkMetrics {
+    /// Derives the rates and the lag from a worker's collector and source at `now` (ns since the
+    /// start of the schedule).
+    ///
+    /// The offered rate is measured over the part of the schedule that was injected, so it does
+    /// not drop when the source falls behind.
+    pub fn new<I: InputTimeResumableIterator<u64>, M: Metrics<u64>, J: InputTimeResumableIterator<u64>>(
+        latency: HDRHist,
+        collector: &MetricCollector<u64, I, M>,
+        source: &mut SyntheticInputTimeGenerator<u64, J>,
+        now: u64) -> Self {
+
+        let next_scheduled = source.next_scheduled();
+        let scheduled_span = next_scheduled.map_or(now, |t| t.min(now));
+        let per_second = |count: u64, span: u64| if span == 0 { 0f64 } else { count as f64 * 1_000_000_000f64 / span as f64 };
+        Self {
+            latency,
+            recorded_samples: collector.recorded_samples(),
+            offered_rate: per_second(source.injected(), scheduled_span),
+            achieved_rate: per_second(collector.recorded_samples() as u64, now),
+            injection_lag_ns: next_scheduled.map_or(0, |t| now.saturating_sub(t)),
+        }
+    }
+}
+
+/// Serves the latest published `BenchmarkMetrics` in the Prometheus text exposition format.
+///
+/// Cloning is cheap and yields a handle to the same set of metrics, so each worker can hold one.
+#[derive(Clone, Default)]
+pub struct PrometheusExporter {
+    workers: Arc<Mutex<BTreeMap<String, BenchmarkMetrics>>>,
+}
+
+impl PrometheusExporter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn publish(&self, worker: impl Into<String>, metrics: BenchmarkMetrics) {
+        self.workers.lock().expect("poisoned lock").insert(worker.into(), metrics);
+    }
+
+    pub fn render(&self) -> String {
+        let workers = self.workers.lock().expect("poisoned lock");
+        let mut out = String::new();
+
+        header(&mut out, "streaming_harness_latency_nanoseconds", "gauge",
+               "Upper bound of the end-to-end latency percentile.");
+        for (worker, metrics) in workers.iter() {
+            let quantiles = metrics.latency.quantiles(EXPORTED_PERCENTILES.iter().map(|&(_, q)| q));
+            for (&(percentile, _), (_, _, upper_bound)) in EXPORTED_PERCENTILES.iter().zip(quantiles) {
+                writeln!(out, "streaming_harness_latency_nanoseconds{{worker=\"{}\",percentile=\"{}\"}} {}",
+                         escape(worker), percentile, upper_bound).unwrap();
+            }
+        }
+
+        header(&mut out, "streaming_harness_recorded_samples_total", "counter",
+               "Acknowledged input records.");
+        for (worker, metrics) in workers.iter() {
+            sample(&mut out, "streaming_harness_recorded_samples_total", worker, metrics.recorded_samples as f64);
+        }
+
+        header(&mut out, "streaming_harness_offered_rate", "gauge",
+               "Scheduled input records per second.");
+        for (worker, metrics) in workers.iter() {
+            sample(&mut out, "streaming_harness_offered_rate", worker, metrics.offered_rate);
+        }
+
+        header(&mut out, "streaming_harness_achieved_rate", "gauge",
+               "Acknowledged input records per second.");
+        for (worker, metrics) in workers.iter() {
+            sample(&mut out, "streaming_harness_achieved_rate", worker, metrics.achieved_rate);
+        }
+
+        header(&mut out, "streaming_harness_injection_lag_nanoseconds", "gauge",
+               "Delay of the source with respect to the input schedule.");
+        for (worker, metrics) in workers.iter() {
+            sample(&mut out, "streaming_harness_injection_lag_nanoseconds", worker, metrics.injection_lag_ns as f64);
+        }
+
+        out
+    }
+
+    /// Atomically replaces `path` with the current metrics, for node_exporter's textfile collector.
+    pub fn write_textfile(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        ::std::fs::write(&tmp, self.render())?;
+        ::std::fs::rename(&tmp, path)
+    }
+
+    /// Answers every HTTP request on `addr` with the current metrics from a background thread.
+    ///
+    /// Returns the bound address, which is useful when binding to port 0.
+    pub fn serve(&self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let exporter = self.clone();
+        ::std::thread::Builder::new().name("prometheus-exporter".to_string()).spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                // a stalled client must not block the single accepting thread
+                if stream.set_read_timeout(Some(STREAM_TIMEOUT)).is_err() || stream.set_write_timeout(Some(STREAM_TIMEOUT)).is_err() {
+                    continue;
+                }
+                let mut request = [0u8; 1024];
+                // the request itself is irrelevant, every path serves the metrics
+                let _ = stream.read(&mut request);
+                let body = exporter.render();
+                let _ = write!(stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body);
+            }
+        })?;
+        Ok(local_addr)
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn sample(out: &mut String, name: &str, worker: &str, value: f64) {
+    writeln!(out, "{}{{worker=\"{}\"}} {}", name, escape(worker), value).unwrap();
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_exposition_text() {
+        let mut latency = HDRHist::new();
+        latency.add_value(1_000);
+        let exporter = PrometheusExporter::new();
+        exporter.publish("worker \"0\"\\", BenchmarkMetrics {
+            latency,
+            recorded_samples: 3,
+            offered_rate: 10.5,
+            achieved_rate: 10.0,
+            injection_lag_ns: 7,
+        });
+        let text = exporter.render();
+        let lines = text.lines().collect::<Vec<_>>();
+        let worker = "worker=\"worker \\\"0\\\"\\\\\"";
+        assert!(lines.contains(&"# HELP streaming_harness_latency_nanoseconds Upper bound of the end-to-end latency percentile."));
+        assert!(lines.contains(&"# TYPE streaming_harness_latency_nanoseconds gauge"));
+        assert!(lines.contains(&"# TYPE streaming_harness_recorded_samples_total counter"));
+        for percentile in ["50", "90", "99", "99.9", "100"].iter() {
+            let prefix = format!("streaming_harness_latency_nanoseconds{{{},percentile=\"{}\"}} ", worker, percentile);
+            assert!(lines.iter().any(|l| l.starts_with(&prefix)), "missing {}", prefix);
+        }
+        assert!(!text.contains("quantile="));
+        assert!(lines.contains(&&*format!("streaming_harness_recorded_samples_total{{{}}} 3", worker)));
+        assert!(lines.contains(&&*format!("streaming_harness_offered_rate{{{}}} 10.5", worker)));
+        assert!(lines.contains(&&*format!("streaming_harness_injection_lag_nanoseconds{{{}}} 7", worker)));
+    }
+}
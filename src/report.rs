@@ -0,0 +1,243 @@
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use format::format_summary_timeline;
+use histogram::{Distribution, SUMMARY_QUANTILES};
+use input::{InputTimeResumableIterator, SyntheticInputTimeGenerator};
+use output::{Metrics, MetricCollector};
+use plot::{self, Chart, Scale, escape};
+use timeline::TimelineElement;
+
+struct RunSection {
+    label: String,
+    interval_samples: usize,
+    summary: Vec<u64>,
+    timeline_svg: String,
+    summary_timeline: String,
+}
+
+/// A single HTML file (with inline SVG charts) describing a run or a sweep: configuration,
+/// summary percentiles, per-interval latency and throughput, CCDFs, warnings and environment.
+pub struct Report {
+    title: String,
+    config: Vec<(String, String)>,
+    environment: Vec<(String, String)>,
+    warnings: Vec<String>,
+    runs: Vec<RunSection>,
+    ccdf: Chart,
+    throughput: Chart,
+    sweeps: Vec<String>,
+}
+
+impl Report {
+    /// The environment section starts with the host, OS, architecture, CPUs and creation time.
+    pub fn new(title: impl Into<String>) -> Self {
+        let mut report = Self {
+            title: title.into(),
+            config: Vec::new(),
+            environment: Vec::new(),
+            warnings: Vec::new(),
+            runs: Vec::new(),
+            ccdf: Chart::new("Latency CCDF", "latency (ns)", Scale::Log, "fraction of samples", Scale::Log),
+            throughput: Chart::new("Throughput", "time (s)", Scale::Linear, "records/s", Scale::Linear),
+            sweeps: Vec::new(),
+        };
+        if let Some(host) = ::std::env::var("HOSTNAME").ok()
+            .or_else(|| ::std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string())) {
+            report.add_environment("host", host);
+        }
+        report.add_environment("os", ::std::env::consts::OS);
+        report.add_environment("arch", ::std::env::consts::ARCH);
+        if let Ok(cpus) = ::std::thread::available_parallelism() {
+            report.add_environment("cpus", cpus.to_string());
+        }
+        if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            report.add_environment("created (unix time)", now.as_secs().to_string());
+        }
+        report.add_environment("streaming-harness", env!("CARGO_PKG_VERSION"));
+        report
+    }
+
+    pub fn add_config(&mut self, key: impl Into<String>, value: impl ToString) {
+        self.config.push((key.into(), value.to_string()));
+    }
+
+    pub fn add_environment(&mut self, key: impl Into<String>, value: impl ToString) {
+        self.environment.push((key.into(), value.to_string()));
+    }
+
+    pub fn add_warning(&mut self, warning: impl Into<String>) {
+        self.warnings.push(warning.into());
+    }
+
+    /// Warns if inputs scheduled before `end` were never acknowledged; they are drained from
+    /// `collector`, so this goes at the end of the run.
+    pub fn warn_unacknowledged<I: InputTimeResumableIterator<u64>, M: Metrics<u64>>(
+        &mut self,
+        collector: &mut MetricCollector<u64, I, M>,
+        end: u64) {
+
+        let unacknowledged = collector.drain_unacknowledged(end);
+        if unacknowledged > 0 {
+            self.add_warning(format!("{} inputs were never acknowledged", unacknowledged));
+        }
+    }
+
+    /// Warns if, at `now`, the source is more than `tolerance_ns` behind its input schedule.
+    pub fn warn_source_lag<I: InputTimeResumableIterator<u64>>(
+        &mut self,
+        source: &mut SyntheticInputTimeGenerator<u64, I>,
+        now: u64,
+        tolerance_ns: u64) {
+
+        let lag_ns = source.next_scheduled().map_or(0, |t| now.saturating_sub(t));
+        if lag_ns > tolerance_ns {
+            self.add_warning(format!("the source fell {} ns behind its input schedule", lag_ns));
+        }
+    }
+
+    /// Adds a run: the summary and CCDF are of `overall`, the distribution of the whole run
+    /// (e.g. `Timeline::latency_metrics`), and the charts per interval are of `timeline`, with
+    /// intervals of `timeline_dt` ns. The summary's sample count is that of `timeline`, which
+    /// may cover less of the run than `overall` (e.g. without warmup).
+    pub fn add_run<M: Distribution, TM: Metrics<u64>+Distribution+Clone>(
+        &mut self,
+        label: impl Into<String>,
+        overall: &M,
+        timeline: &[TimelineElement<u64, TM>],
+        timeline_dt: u64) {
+
+        let label = label.into();
+        self.ccdf.add_series(label.clone(), overall.ccdf_upper_bounds().into_iter().map(|(v, f)| (v as f64, f)).collect());
+        self.throughput.add_series(label.clone(), timeline.iter()
+            .map(|e| (e.time as f64 / 1_000_000_000f64, e.samples as f64 * 1_000_000_000f64 / timeline_dt as f64))
+            .collect());
+        self.runs.push(RunSection {
+            interval_samples: timeline.iter().map(|e| e.samples).sum(),
+            summary: overall.summary_upper_bounds(),
+            timeline_svg: plot::percentile_timeline_chart(format!("{}: latency per interval", label),
+                timeline, &[0.5, 0.99, 0.999, 1.0]).to_svg(),
+            summary_timeline: format_summary_timeline(label.clone(), timeline.to_vec()),
+            label,
+        });
+    }
+
+    /// Adds the latency-throughput curve of a sweep, one `(throughput, metrics)` pair per run.
+    pub fn add_sweep<M: Distribution>(&mut self, title: impl Into<String>, runs: &[(f64, &M)]) {
+        self.sweeps.push(plot::latency_throughput_chart(title, runs, &[0.5, 0.99, 0.999]).to_svg());
+    }
+
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", escape(&self.title)).unwrap();
+        html.push_str("<style>\nbody { font-family: sans-serif; margin: 2em; }\n\
+                       table { border-collapse: collapse; margin-bottom: 1em; }\n\
+                       th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }\n\
+                       th:first-child, td:first-child { text-align: left; }\n\
+                       .warning { color: #a00; }\n</style>\n</head>\n<body>\n");
+        writeln!(html, "<h1>{}</h1>", escape(&self.title)).unwrap();
+
+        if !self.warnings.is_empty() {
+            html.push_str("<h2>Warnings</h2>\n<ul>\n");
+            for warning in self.warnings.iter() {
+                writeln!(html, "<li class=\"warning\">{}</li>", escape(warning)).unwrap();
+            }
+            html.push_str("</ul>\n");
+        }
+
+        html.push_str("<h2>Configuration</h2>\n");
+        key_value_table(&mut html, &self.config);
+
+        if !self.runs.is_empty() {
+            html.push_str("<h2>Summary (whole-run latency upper bounds, ns)</h2>\n<table>\n<tr><th>run</th><th>samples in intervals</th>");
+            for quantile in SUMMARY_QUANTILES.iter() {
+                writeln!(html, "<th>{}</th>", quantile).unwrap();
+            }
+            html.push_str("</tr>\n");
+            for run in self.runs.iter() {
+                write!(html, "<tr><td>{}</td><td>{}</td>", escape(&run.label), run.interval_samples).unwrap();
+                for upper_bound in run.summary.iter() {
+                    write!(html, "<td>{}</td>", upper_bound).unwrap();
+                }
+                html.push_str("</tr>\n");
+            }
+            html.push_str("</table>\n");
+
+            html.push_str("<h2>Latency</h2>\n");
+            html.push_str(&self.ccdf.to_svg());
+            for run in self.runs.iter() {
+                html.push_str(&run.timeline_svg);
+            }
+            html.push_str("<h2>Throughput</h2>\n");
+            html.push_str(&self.throughput.to_svg());
+        }
+
+        if !self.sweeps.is_empty() {
+            html.push_str("<h2>Sweeps</h2>\n");
+            for sweep in self.sweeps.iter() {
+                html.push_str(sweep);
+            }
+        }
+
+        html.push_str("<h2>Environment</h2>\n");
+        key_value_table(&mut html, &self.environment);
+
+        if !self.runs.is_empty() {
+            html.push_str("<details>\n<summary>Summary timelines (tab separated)</summary>\n<pre>");
+            for run in self.runs.iter() {
+                writeln!(html, "{}", escape(&run.summary_timeline)).unwrap();
+            }
+            html.push_str("</pre>\n</details>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        ::std::fs::write(path, self.render())
+    }
+}
+
+fn key_value_table(html: &mut String, entries: &[(String, String)]) {
+    html.push_str("<table>\n");
+    for (key, value) in entries.iter() {
+        writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", escape(key), escape(value)).unwrap();
+    }
+    html.push_str("</table>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use histogram::SortedSamples;
+    use input::ConstantThroughputInputTimes;
+
+    #[test]
+    fn warnings_from_collector_and_source() {
+        let mut report = Report::new("test");
+        let mut collector = MetricCollector::new(ConstantThroughputInputTimes::<u64, u64>::new(0, 10, 100), SortedSamples::new());
+        collector.acknowledge_till_input_t(50, 40);
+        report.warn_unacknowledged(&mut collector, 80);
+        let mut source = SyntheticInputTimeGenerator::new(ConstantThroughputInputTimes::<u64, u64>::new(0, 10, 100));
+        assert_eq!(source.iter_until(30).unwrap().count(), 3);
+        report.warn_source_lag(&mut source, 60, 100);
+        report.warn_source_lag(&mut source, 200, 100);
+        assert_eq!(report.warnings, vec![
+            "3 inputs were never acknowledged".to_string(),
+            "the source fell 170 ns behind its input schedule".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn summary_of_the_whole_run() {
+        let mut overall = SortedSamples::new();
+        overall.record(0, 1000);
+        let mut report = Report::new("test");
+        report.add_run("run", &overall, &[TimelineElement { time: 0, metrics: SortedSamples::new(), samples: 0 }], 10);
+        assert_eq!(report.runs[0].summary, vec![1000; SUMMARY_QUANTILES.len()]);
+        assert_eq!(report.runs[0].interval_samples, 0);
+        assert!(report.render().contains("<th>samples in intervals</th>"));
+    }
+}
@@ -1,105 +1,548 @@
-use super::output::Metrics;
+use super::output::{Metrics, CombineError};
 use std::ops::{Add, Sub};
-use num_traits::{Zero, Bounded};
+
+use util::{ToNanos, FromNanos};
 
 #[derive(Debug, Clone)]
-pub struct TimelineElement<T: Eq+Ord+Copy+Zero+Bounded, M: Metrics<T>> {
+pub struct TimelineElement<T: Eq+Ord+Copy, M: Metrics<T>> {
     pub time: T,
     pub metrics: M,
     pub samples: usize,
 }
 
-impl<T: Eq+Ord+Copy+Zero+Bounded, M: Metrics<T>> TimelineElement<T, M> {
-    pub fn combined(mut self, other: Self) -> Self {
-        assert!(self.time == other.time, "self.time != other.time");
-        self.metrics = self.metrics.combined(other.metrics);
-        self.samples = self.samples + other.samples;
-        self
+impl<T: Eq+Ord+Copy+::std::fmt::Debug, M: Metrics<T>> TimelineElement<T, M> {
+    pub fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_combined(self, other: Self) -> Result<Self, CombineError> {
+        if self.time != other.time {
+            return Err(CombineError::mismatch("time", self.time, other.time));
+        }
+        Ok(TimelineElement {
+            time: self.time,
+            metrics: self.metrics.try_combined(other.metrics)?,
+            samples: self.samples + other.samples,
+        })
     }
 }
 
+/// Combines the elements of a timeline with resolution `timeline_dt` into elements `factor` times
+/// as long, for a coarser view. Combined elements start at `origin` plus a multiple of
+/// `factor * timeline_dt`, so that rollups of timelines with a common origin line up.
+pub fn rollup<
+    T: Eq+Ord+Copy+::std::fmt::Debug+Sub<T, Output=DT>+Sub<DT, Output=T>,
+    DT: ToNanos+FromNanos,
+    M: Metrics<T>>(
+    timeline: Vec<TimelineElement<T, M>>, origin: T, timeline_dt: DT, factor: usize) -> Vec<TimelineElement<T, M>> {
+
+    assert!(factor > 0, "factor must be positive");
+    let span = timeline_dt.to_nanos() * factor as u64;
+    let mut rolled_up: Vec<TimelineElement<T, M>> = Vec::with_capacity(timeline.len() / factor + 1);
+    for element in timeline {
+        assert!(element.time >= origin, "elements must not precede the origin");
+        let time = element.time - DT::from_nanos((element.time - origin).to_nanos() % span);
+        let element = TimelineElement { time, ..element };
+        if rolled_up.last().map(|last| last.time) == Some(time) {
+            let last = rolled_up.pop().unwrap();
+            rolled_up.push(last.combined(element));
+        } else {
+            rolled_up.push(element);
+        }
+    }
+    rolled_up
+}
+
+/// Called with each timeline element once recording has moved past its interval.
+pub type IntervalReporter<T, M> = Box<dyn FnMut(&TimelineElement<T, M>)+Send>;
+
+pub type MetricsFactory<M> = Box<dyn Fn()->M+Send>;
+
+/// Which of a sample's times determines the timeline element it is recorded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bucketing {
+    /// Group by `begin_t`, when the input was scheduled.
+    InputTime,
+    /// Group by `end_t`, when the input was acknowledged.
+    CompletionTime,
+}
+
 pub struct Timeline<
-    T: Eq+Ord+Copy+Zero+Bounded+Add<DT, Output=T>+Sub<T, Output=T>,
+    T: Eq+Ord+Copy+Add<DT, Output=T>,
     DT: Copy,
     M: Metrics<T>,
     TM: Metrics<T>> {
 
     pub latency_metrics: M,
     timeline_dt: DT,
+    start_t: T,
     cur_element: usize,
     cur_element_t: T,
     pub timeline: Vec<TimelineElement<T, TM>>,
+    /// Samples bucketed before the start of the timeline; its `time` is the start of the timeline.
+    pub underflow: TimelineElement<T, TM>,
+    /// Samples bucketed past the preallocated end; always empty for growable timelines.
+    pub overflow: TimelineElement<T, TM>,
+    bucketing: Bucketing,
+    // set for growable timelines only
+    metrics_factory: Option<MetricsFactory<TM>>,
+    growable: bool,
+    reporter: Option<IntervalReporter<T, TM>>,
 }
 
 impl<
-    T: Eq+Ord+Copy+Zero+Bounded+Add<DT, Output=T>+Sub<T, Output=T>,
+    T: Eq+Ord+Copy+Add<DT, Output=T>,
     DT: Copy,
     M: Metrics<T>,
     TM: Metrics<T>> Timeline<T, DT, M, TM> {
 
     pub fn new(start_t: T, end_t: T, timeline_dt: DT, latency_metrics: M, timeline_metrics: impl Fn()->TM) -> Self {
+        let timeline: Vec<_> = (0..).scan(start_t, |t, _| {
+            let cur = *t;
+            *t = *t + timeline_dt;
+            Some(cur)
+        }).take_while(|t| *t < end_t).map(|time| TimelineElement {
+            time,
+            metrics: timeline_metrics(),
+            samples: 0,
+        }).collect();
+        let overflow_t = timeline.last().map(|e| e.time + timeline_dt).unwrap_or(start_t);
         Self {
             latency_metrics,
             timeline_dt,
+            start_t,
             cur_element: 0usize,
             cur_element_t: start_t,
-            timeline: (0..).scan(start_t, |t, _| {
-                let cur = *t;
-                *t = *t + timeline_dt;
-                Some(cur)
-            }).take_while(|t| *t < end_t).map(|time| TimelineElement {
-                time,
+            timeline,
+            underflow: TimelineElement {
+                time: start_t,
+                metrics: timeline_metrics(),
+                samples: 0,
+            },
+            overflow: TimelineElement {
+                time: overflow_t,
                 metrics: timeline_metrics(),
                 samples: 0,
-            }).collect(),
+            },
+            bucketing: Bucketing::InputTime,
+            metrics_factory: None,
+            growable: false,
+            reporter: None,
+        }
+    }
+
+    /// Timelines bucket by input time unless configured otherwise. Recording into a pair of
+    /// timelines, e.g. `(by_input_time, by_completion_time)`, gives both views of the same samples.
+    pub fn bucketed_by(mut self, bucketing: Bucketing) -> Self {
+        self.bucketing = bucketing;
+        self
+    }
+
+    pub fn bucketing(&self) -> Bucketing {
+        self.bucketing
+    }
+
+    /// The timeline at `factor` times its recorded resolution, see `rollup`.
+    pub fn rollup(&self, origin: T, factor: usize) -> Vec<TimelineElement<T, TM>>
+        where T: ::std::fmt::Debug+Sub<T, Output=DT>+Sub<DT, Output=T>, DT: ToNanos+FromNanos, TM: Clone {
+
+        rollup(self.timeline.clone(), origin, self.timeline_dt, factor)
+    }
+
+    /// A timeline that starts empty and allocates elements as samples for later intervals are
+    /// recorded, so that it never overflows.
+    pub fn new_growable(start_t: T, timeline_dt: DT, latency_metrics: M, timeline_metrics: impl Fn()->TM+Send+'static) -> Self {
+        let mut timeline = Self::new(start_t, start_t, timeline_dt, latency_metrics, &timeline_metrics);
+        timeline.metrics_factory = Some(Box::new(timeline_metrics));
+        timeline.growable = true;
+        timeline
+    }
+
+    /// Reports every completed interval while the experiment is still running, e.g. to print
+    /// progress or to stop a run early. An interval is complete when a sample for a later
+    /// interval is recorded, or when time passes its end: `flush_inputs_until` for timelines
+    /// by input time, `flush_until` for timelines by completion time (`MetricCollector::flush_until`
+    /// calls both), so that intervals are reported even if nothing is acknowledged. Samples
+    /// recorded for an interval after it was reported still count in the timeline.
+    pub fn set_reporter(&mut self, reporter: impl FnMut(&TimelineElement<T, TM>)+Send+'static) {
+        self.reporter = Some(Box::new(reporter));
+    }
+
+    /// Reports the current interval, e.g. once the experiment is over.
+    pub fn finish(&mut self) {
+        let next_t = self.cur_element_t + self.timeline_dt;
+        self.advance_to(next_t);
+    }
+
+    // reports the intervals that end at or before `t` and moves past them
+    fn advance_to(&mut self, t: T) {
+        if t < self.start_t {
+            return;
+        }
+        while t >= self.cur_element_t + self.timeline_dt {
+            let cur_element = self.cur_element;
+            self.grow_to(cur_element);
+            if let Some(ref mut reporter) = self.reporter {
+                if let Some(element) = self.timeline.get(self.cur_element) {
+                    reporter(element);
+                }
+            }
+            self.cur_element_t = self.cur_element_t + self.timeline_dt;
+            self.cur_element += 1;
+        }
+    }
+
+    // a late sample for an interval that a flush or `finish` already reported
+    #[cold]
+    fn late_index(&self, bucket_t: T) -> usize {
+        match self.timeline.iter().rposition(|e| e.time <= bucket_t) {
+            Some(index) if bucket_t < self.timeline[index].time + self.timeline_dt => index,
+            _ => self.timeline.len(),
+        }
+    }
+
+    fn grow_to(&mut self, index: usize) {
+        if !self.growable {
+            return;
+        }
+        if let Some(ref metrics_factory) = self.metrics_factory {
+            while index >= self.timeline.len() {
+                let time = self.timeline.last().map(|e| e.time + self.timeline_dt).unwrap_or(self.start_t);
+                self.timeline.push(TimelineElement {
+                    time,
+                    metrics: metrics_factory(),
+                    samples: 0,
+                });
+            }
         }
     }
 }
 
 impl<
-    T: Eq+Ord+Copy+Zero+Bounded+Add<DT, Output=T>+Sub<T, Output=T>,
+    T: Eq+Ord+Copy+Add<DT, Output=T>+::std::fmt::Debug,
     DT: Copy+Eq+::std::fmt::Debug,
     M: Metrics<T>,
-    TM: Metrics<T>> Metrics<T> for Timeline<T, DT, M, TM> {
+    TM: Metrics<T>> Timeline<T, DT, M, TM> {
 
-    #[inline(always)]
-    fn record(&mut self, begin_t: T, end_t: T) {
-        self.latency_metrics.record(begin_t, end_t);
-        while begin_t >= self.cur_element_t + self.timeline_dt {
-            self.cur_element_t = self.cur_element_t + self.timeline_dt;
-            self.cur_element += 1;
-        }
-        let TimelineElement {
-            ref mut metrics,
-            ref mut samples,
-            ..
-        } = &mut self.timeline[self.cur_element];
-        metrics.record(begin_t, end_t);
-        *samples += 1;
+    /// Combines two timelines by aligning their elements by `time`, so that timelines with
+    /// different start points and lengths lose no data. The result spans both timelines, with
+    /// empty elements from `timeline_metrics` wherever neither has one.
+    ///
+    /// `underflow` and `overflow` are combined with each other only: they keep no times to
+    /// redistribute their samples by, even where the other timeline has elements.
+    ///
+    /// Fails if the timelines differ in `timeline_dt` or bucketing, or if their elements are not
+    /// on the same grid. The result keeps `self`'s reporter, and `self`'s or else `other`'s
+    /// metrics factory.
+    pub fn try_combined_with(self, other: Self, timeline_metrics: impl Fn()->TM) -> Result<Self, CombineError> {
+        self.combine(other, Some(&timeline_metrics))
     }
 
-    fn combined(self, other: Self) -> Self {
+    // `timeline_metrics`, or else either timeline's metrics factory, fills the gaps
+    fn combine(self, other: Self, timeline_metrics: Option<&dyn Fn()->TM>) -> Result<Self, CombineError> {
         let Timeline {
             timeline,
             latency_metrics,
             timeline_dt,
-            cur_element,
-            cur_element_t,
+            start_t: self_start_t,
+            underflow,
+            overflow,
+            bucketing,
+            metrics_factory,
+            growable,
+            reporter,
+            ..
         } = self;
         let Timeline {
             timeline: other_timeline,
             latency_metrics: other_latency_metrics,
             timeline_dt: other_timeline_dt,
+            start_t: other_start_t,
+            underflow: other_underflow,
+            overflow: other_overflow,
+            bucketing: other_bucketing,
+            metrics_factory: other_metrics_factory,
             ..
         } = other;
-        assert_eq!(timeline_dt, other_timeline_dt);
-        Timeline {
-            timeline: 
-                timeline.into_iter().zip(other_timeline.into_iter()).map(|(s, m)| s.combined(m)).collect(),
-            latency_metrics: latency_metrics.combined(other_latency_metrics),
+        let metrics_factory = metrics_factory.or(other_metrics_factory);
+        if timeline_dt != other_timeline_dt {
+            return Err(CombineError::mismatch("timeline_dt", timeline_dt, other_timeline_dt));
+        }
+        if bucketing != other_bucketing {
+            return Err(CombineError::mismatch("bucketing", bucketing, other_bucketing));
+        }
+        let start_t = ::std::cmp::min(self_start_t, other_start_t);
+        let mut aligned_t = start_t;
+        while aligned_t < ::std::cmp::max(self_start_t, other_start_t) {
+            aligned_t = aligned_t + timeline_dt;
+        }
+        if aligned_t != ::std::cmp::max(self_start_t, other_start_t) {
+            return Err(CombineError::mismatch("start_t alignment", self_start_t, other_start_t));
+        }
+        let mut combined = Vec::with_capacity(::std::cmp::max(timeline.len(), other_timeline.len()));
+        let mut next_t = start_t;
+        let mut left = timeline.into_iter().peekable();
+        let mut right = other_timeline.into_iter().peekable();
+        loop {
+            let next = match (left.peek().map(|e| e.time), right.peek().map(|e| e.time)) {
+                (Some(l), Some(r)) if l == r => left.next().unwrap().try_combined(right.next().unwrap())?,
+                (Some(l), Some(r)) if l < r => {
+                    if r < l + timeline_dt {
+                        return Err(CombineError::mismatch("element alignment", l, r));
+                    }
+                    left.next().unwrap()
+                },
+                (Some(l), Some(r)) => {
+                    if l < r + timeline_dt {
+                        return Err(CombineError::mismatch("element alignment", l, r));
+                    }
+                    right.next().unwrap()
+                },
+                (Some(_), None) => left.next().unwrap(),
+                (None, Some(_)) => right.next().unwrap(),
+                (None, None) => break,
+            };
+            while next_t < next.time {
+                let metrics = match timeline_metrics.or_else(|| metrics_factory.as_ref().map(|f| &**f as &dyn Fn()->TM)) {
+                    Some(timeline_metrics) => timeline_metrics(),
+                    None => return Err(CombineError::Gap {
+                        start: format!("{:?}", next_t),
+                        end: format!("{:?}", next.time),
+                    }),
+                };
+                combined.push(TimelineElement {
+                    time: next_t,
+                    metrics,
+                    samples: 0,
+                });
+                next_t = next_t + timeline_dt;
+            }
+            next_t = next.time + timeline_dt;
+            combined.push(next);
+        }
+        let overflow_t = combined.last().map(|e| e.time + timeline_dt).unwrap_or(start_t);
+        Ok(Timeline {
+            timeline: combined,
+            latency_metrics: latency_metrics.try_combined(other_latency_metrics)?,
             timeline_dt,
-            cur_element,
-            cur_element_t,
+            start_t,
+            cur_element: 0usize,
+            cur_element_t: start_t,
+            underflow: TimelineElement {
+                time: start_t,
+                metrics: underflow.metrics.try_combined(other_underflow.metrics)?,
+                samples: underflow.samples + other_underflow.samples,
+            },
+            overflow: TimelineElement {
+                time: overflow_t,
+                metrics: overflow.metrics.try_combined(other_overflow.metrics)?,
+                samples: overflow.samples + other_overflow.samples,
+            },
+            bucketing,
+            metrics_factory,
+            growable,
+            reporter,
+        })
+    }
+}
+
+impl<
+    T: Eq+Ord+Copy+Add<DT, Output=T>+::std::fmt::Debug,
+    DT: Copy+Eq+::std::fmt::Debug,
+    M: Metrics<T>,
+    TM: Metrics<T>> Metrics<T> for Timeline<T, DT, M, TM> {
+
+    #[inline(always)]
+    fn record(&mut self, begin_t: T, end_t: T) {
+        self.latency_metrics.record(begin_t, end_t);
+        let bucket_t = match self.bucketing {
+            Bucketing::InputTime => begin_t,
+            Bucketing::CompletionTime => end_t,
+        };
+        if bucket_t < self.start_t {
+            self.underflow.metrics.record(begin_t, end_t);
+            self.underflow.samples += 1;
+            return;
         }
+        self.advance_to(bucket_t);
+        let index = if bucket_t >= self.cur_element_t {
+            self.cur_element
+        } else {
+            self.late_index(bucket_t)
+        };
+        self.grow_to(index);
+        let TimelineElement {
+            ref mut metrics,
+            ref mut samples,
+            ..
+        } = self.timeline.get_mut(index).unwrap_or(&mut self.overflow);
+        metrics.record(begin_t, end_t);
+        *samples += 1;
+    }
+
+    fn flush_until(&mut self, now: T) {
+        self.latency_metrics.flush_until(now);
+        if self.bucketing == Bucketing::CompletionTime {
+            self.advance_to(now);
+        }
+    }
+
+    fn flush_inputs_until(&mut self, input_t: T) {
+        self.latency_metrics.flush_inputs_until(input_t);
+        if self.bucketing == Bucketing::InputTime {
+            self.advance_to(input_t);
+        }
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// See `try_combined_with`; gaps are filled with the metrics factory of either timeline,
+    /// which only growable timelines have.
+    fn try_combined(self, other: Self) -> Result<Self, CombineError> {
+        self.combine(other, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use histogram::SortedSamples;
+
+    fn element(time: u64, samples: usize) -> TimelineElement<u64, SortedSamples> {
+        let mut metrics = SortedSamples::new();
+        for _ in 0..samples {
+            metrics.record(time, time + 1);
+        }
+        TimelineElement { time, metrics, samples }
+    }
+
+    #[test]
+    fn rollup_aligns_to_origin() {
+        let timeline = (3..8).map(|i| element(i * 10, i as usize)).collect();
+        let rolled_up = rollup(timeline, 0, 10, 4);
+        assert_eq!(rolled_up.iter().map(|e| (e.time, e.samples, e.metrics.len())).collect::<Vec<_>>(),
+                   vec![(0, 3, 3), (40, 4 + 5 + 6 + 7, 4 + 5 + 6 + 7)]);
+    }
+
+    fn timeline(start_t: u64, end_t: u64) -> Timeline<u64, u64, SortedSamples, SortedSamples> {
+        Timeline::new(start_t, end_t, 10, SortedSamples::new(), SortedSamples::new)
+    }
+
+    fn samples(timeline: &Timeline<u64, u64, SortedSamples, SortedSamples>) -> Vec<(u64, usize)> {
+        timeline.timeline.iter().map(|e| (e.time, e.samples)).collect()
+    }
+
+    #[test]
+    fn combine_different_starts() {
+        let mut early = timeline(0, 20);
+        early.record(5, 6);
+        let mut late = timeline(40, 60);
+        late.record(55, 56);
+        let combined = late.try_combined_with(early, SortedSamples::new).unwrap();
+        assert_eq!(samples(&combined), vec![(0, 1), (10, 0), (20, 0), (30, 0), (40, 0), (50, 1)]);
+        assert_eq!(combined.overflow.time, 60);
+        assert_eq!(combined.latency_metrics.len(), 2);
+    }
+
+    #[test]
+    fn combine_different_lengths() {
+        let mut short = timeline(0, 20);
+        short.record(15, 16);
+        let mut long = timeline(0, 40);
+        long.record(15, 16);
+        long.record(35, 36);
+        let combined = short.try_combined(long).unwrap();
+        assert_eq!(samples(&combined), vec![(0, 0), (10, 2), (20, 0), (30, 1)]);
+    }
+
+    #[test]
+    fn combine_misaligned_grids() {
+        match timeline(0, 30).try_combined(timeline(5, 35)) {
+            Err(CombineError::Mismatch { what, .. }) => assert_eq!(what, "start_t alignment"),
+            _ => panic!("expected a mismatch"),
+        }
+    }
+
+    #[test]
+    fn combine_with_a_gap() {
+        match timeline(0, 10).try_combined(timeline(30, 40)) {
+            Err(CombineError::Gap { start, end }) => assert_eq!((start.as_str(), end.as_str()), ("10", "30")),
+            _ => panic!("expected a gap"),
+        }
+        // the growable timeline's factory fills the gap
+        let mut growable = Timeline::new_growable(30, 10, SortedSamples::new(), SortedSamples::new);
+        growable.record(35, 36);
+        let combined = timeline(0, 10).try_combined(growable).unwrap();
+        assert_eq!(samples(&combined), vec![(0, 0), (10, 0), (20, 0), (30, 1)]);
+    }
+
+    #[test]
+    fn record_after_combining() {
+        let mut combined = timeline(0, 10).try_combined_with(timeline(30, 40), SortedSamples::new).unwrap();
+        combined.record(25, 26);
+        combined.record(32, 33);
+        combined.record(45, 46);
+        assert_eq!(samples(&combined), vec![(0, 0), (10, 0), (20, 1), (30, 1)]);
+        assert_eq!(combined.overflow.samples, 1);
+    }
+
+    #[test]
+    fn flush_reports_stalled_intervals() {
+        use std::sync::{Arc, Mutex};
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut timeline = timeline(0, 50);
+        let reported_by_reporter = reported.clone();
+        timeline.set_reporter(move |e| reported_by_reporter.lock().unwrap().push((e.time, e.samples)));
+        timeline.record(5, 6);
+        // completion times do not close intervals by input time
+        timeline.flush_until(45);
+        assert!(reported.lock().unwrap().is_empty());
+        timeline.flush_inputs_until(31);
+        assert_eq!(*reported.lock().unwrap(), vec![(0, 1), (10, 0), (20, 0)]);
+        // late samples still land in their own interval
+        timeline.record(15, 40);
+        timeline.finish();
+        assert_eq!(*reported.lock().unwrap(), vec![(0, 1), (10, 0), (20, 0), (30, 0)]);
+        assert_eq!(samples(&timeline), vec![(0, 1), (10, 1), (20, 0), (30, 0), (40, 0)]);
+    }
+
+    type TestCollector = ::output::MetricCollector<
+        u64, ::input::ConstantThroughputInputTimes<u64, u64>, Timeline<u64, u64, SortedSamples, SortedSamples>>;
+
+    type Reported = ::std::sync::Arc<::std::sync::Mutex<Vec<(u64, usize)>>>;
+
+    fn reported_collector(bucketing: Bucketing) -> (TestCollector, Reported) {
+
+        let reported = ::std::sync::Arc::new(::std::sync::Mutex::new(Vec::new()));
+        let reported_by_reporter = reported.clone();
+        let mut timeline = Timeline::new(0, 1_000, 100, SortedSamples::new(), SortedSamples::new).bucketed_by(bucketing);
+        timeline.set_reporter(move |e| reported_by_reporter.lock().unwrap().push((e.time, e.samples)));
+        let input_times = ::input::ConstantThroughputInputTimes::new(0, 10, 1_000);
+        (::output::MetricCollector::new(input_times, timeline), reported)
+    }
+
+    #[test]
+    fn latency_above_the_interval_length() {
+        let (mut collector, reported) = reported_collector(Bucketing::InputTime);
+        // every input is acknowledged 150 after it was scheduled
+        for now in (0..1_200).step_by(10) {
+            if now >= 150 {
+                collector.acknowledge_till_input_t(now, now - 150);
+            }
+            collector.flush_until(now);
+        }
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 10);
+        assert!(reported.iter().all(|&(_, samples)| samples == 10), "{:?}", *reported);
+    }
+
+    #[test]
+    fn completion_time_flushes_by_time() {
+        let (mut collector, reported) = reported_collector(Bucketing::CompletionTime);
+        collector.acknowledge_till_input_t(50, 0);
+        collector.flush_until(250);
+        assert_eq!(*reported.lock().unwrap(), vec![(0, 1), (100, 0)]);
     }
 }
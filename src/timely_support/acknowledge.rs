@@ -1,9 +1,10 @@
 use std::rc::Rc;
-use std::cell::{Cell, RefCell};
+use std::cell::RefCell;
 
 use input::InputTimeResumableIterator;
 use output::{Metrics, MetricCollector};
-use util::ToNanos;
+use load::LoadPhase;
+use clock::Clock;
 
 use timely::Data;
 use timely::dataflow::{Stream, Scope, channels::pact::Pipeline};
@@ -13,29 +14,33 @@ use timely::progress::{nested::product::Product, timestamp::RootTimestamp};
 pub trait Acknowledge<G: Scope<Timestamp=Product<RootTimestamp, u64>>, D: Data> {
     fn acknowledge<
         I: InputTimeResumableIterator<u64>+'static,
-        M: Metrics<u64>+'static>(
+        M: Metrics<u64>+'static,
+        C: Clock+'static>(
             &self,
             metric_collector: Rc<RefCell<MetricCollector<u64, I, M>>>,
-            data_loaded: Rc<Cell<Option<::std::time::Instant>>>) -> Stream<G, D>;
+            load_phase: Rc<LoadPhase<C>>) -> Stream<G, D>;
 }
 
 impl<G: Scope<Timestamp=Product<RootTimestamp, u64>>, D: Data> Acknowledge<G, D> for Stream<G, D> {
     fn acknowledge<
         I: InputTimeResumableIterator<u64>+'static,
-        M: Metrics<u64>+'static>(
+        M: Metrics<u64>+'static,
+        C: Clock+'static>(
             &self,
             metric_collector: Rc<RefCell<MetricCollector<u64, I, M>>>,
-            data_loaded: Rc<Cell<Option<::std::time::Instant>>>) -> Stream<G, D> {
+            load_phase: Rc<LoadPhase<C>>) -> Stream<G, D> {
 
         self.unary_frontier(Pipeline, "Acknowledge", move |_cap, _| {
             move |input, output| {
                 while let Some((time, data)) = input.next() {
                     output.session(&time).give_content(data);
                 }
-                if let Some(elapsed) = data_loaded.get().map(|t| t.elapsed()) {
-                    metric_collector.borrow_mut().acknowledge_while(
-                        elapsed.to_nanos(),
+                if let Some(elapsed) = load_phase.elapsed_ns() {
+                    let mut metric_collector = metric_collector.borrow_mut();
+                    metric_collector.acknowledge_while(
+                        elapsed,
                         |t| !input.frontier().less_than(&RootTimestamp::new(t)));
+                    metric_collector.flush_until(elapsed);
                 }
             }
         })
@@ -4,6 +4,71 @@ pub trait ToNanos {
 
 impl ToNanos for ::std::time::Duration {
     fn to_nanos(&self) -> u64 {
-        self.as_secs() as u64 * 1_000_000_000 + self.subsec_nanos() as u64
+        self.as_secs() * 1_000_000_000 + self.subsec_nanos() as u64
+    }
+}
+
+impl ToNanos for u64 {
+    fn to_nanos(&self) -> u64 {
+        *self
+    }
+}
+
+pub trait FromNanos {
+    fn from_nanos(nanos: u64) -> Self;
+}
+
+impl FromNanos for ::std::time::Duration {
+    fn from_nanos(nanos: u64) -> Self {
+        ::std::time::Duration::from_nanos(nanos)
+    }
+}
+
+impl FromNanos for u64 {
+    fn from_nanos(nanos: u64) -> Self {
+        nanos
+    }
+}
+
+/// The unit times and latencies are reported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl TimeUnit {
+    pub fn nanos(self) -> u64 {
+        match self {
+            TimeUnit::Nanoseconds => 1,
+            TimeUnit::Microseconds => 1_000,
+            TimeUnit::Milliseconds => 1_000_000,
+            TimeUnit::Seconds => 1_000_000_000,
+        }
+    }
+
+    pub fn from_nanos(self, nanos: u64) -> f64 {
+        nanos as f64 / self.nanos() as f64
+    }
+
+    pub fn to_nanos(self, value: f64) -> u64 {
+        (value * self.nanos() as f64) as u64
+    }
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TimeUnit::Nanoseconds => "ns",
+            TimeUnit::Microseconds => "us",
+            TimeUnit::Milliseconds => "ms",
+            TimeUnit::Seconds => "s",
+        }
+    }
+}
+
+impl ::std::fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str(self.suffix())
     }
 }
@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::ops::Add;
+
+use super::output::{Metrics, CombineError};
+use super::timeline::{TimelineElement, MetricsFactory};
+use super::histogram::{Distribution, bucket_count, bucket_index, bucket_value};
+
+/// Keeps the samples that completed in the last `slots` intervals of length `slot_dt`.
+///
+/// Slots are aligned to `start_t`, so windows from different workers can be combined.
+pub struct SlidingWindowMetrics<T: Eq+Ord+Copy+Add<DT, Output=T>, DT: Copy, M: Metrics<T>> {
+    start_t: T,
+    slot_dt: DT,
+    slots: usize,
+    window: VecDeque<TimelineElement<T, M>>,
+    metrics: MetricsFactory<M>,
+}
+
+impl<T: Eq+Ord+Copy+Add<DT, Output=T>, DT: Copy, M: Metrics<T>> SlidingWindowMetrics<T, DT, M> {
+    pub fn new(start_t: T, slot_dt: DT, slots: usize, metrics: impl Fn()->M+Send+'static) -> Self {
+        assert!(slots > 0, "slots must be positive");
+        Self {
+            start_t,
+            slot_dt,
+            slots,
+            window: VecDeque::with_capacity(slots + 1),
+            metrics: Box::new(metrics),
+        }
+    }
+
+    /// Slides the window so that it ends with the slot containing `now`.
+    pub fn advance_to(&mut self, now: T) {
+        if now < self.start_t {
+            return;
+        }
+        if self.window.is_empty() {
+            self.window.push_back(TimelineElement {
+                time: self.start_t,
+                metrics: (self.metrics)(),
+                samples: 0,
+            });
+        }
+        while now >= self.window.back().unwrap().time + self.slot_dt {
+            let time = self.window.back().unwrap().time + self.slot_dt;
+            self.window.push_back(TimelineElement {
+                time,
+                metrics: (self.metrics)(),
+                samples: 0,
+            });
+            if self.window.len() > self.slots {
+                self.window.pop_front();
+            }
+        }
+    }
+
+    /// Samples recorded in the window.
+    pub fn samples(&self) -> usize {
+        self.window.iter().map(|e| e.samples).sum()
+    }
+
+    /// The combined metrics of all slots in the window, `None` until the window reaches
+    /// `start_t` (by recording or `flush_until`); the metrics are empty if the window's slots are.
+    pub fn current(&self) -> Option<M> where M: Clone {
+        let mut slots = self.window.iter().map(|e| e.metrics.clone());
+        let first = slots.next()?;
+        Some(slots.fold(first, |a, b| a.combined(b)))
+    }
+}
+
+impl<T: Eq+Ord+Copy+Add<DT, Output=T>+::std::fmt::Debug, DT: Copy+Eq+::std::fmt::Debug, M: Metrics<T>> Metrics<T> for SlidingWindowMetrics<T, DT, M> {
+    #[inline(always)]
+    fn record(&mut self, begin_t: T, end_t: T) {
+        self.advance_to(end_t);
+        // samples that completed before the window are dropped
+        if let Some(element) = self.window.iter_mut().rev().find(|e| e.time <= end_t) {
+            element.metrics.record(begin_t, end_t);
+            element.samples += 1;
+        }
+    }
+
+    fn flush_until(&mut self, now: T) {
+        self.advance_to(now);
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_combined(self, other: Self) -> Result<Self, CombineError> {
+        let SlidingWindowMetrics {
+            start_t,
+            slot_dt,
+            slots,
+            window,
+            metrics,
+        } = self;
+        if start_t != other.start_t {
+            return Err(CombineError::mismatch("start_t", start_t, other.start_t));
+        }
+        if slot_dt != other.slot_dt {
+            return Err(CombineError::mismatch("slot_dt", slot_dt, other.slot_dt));
+        }
+        if slots != other.slots {
+            return Err(CombineError::mismatch("slots", slots, other.slots));
+        }
+        let mut combined = VecDeque::with_capacity(slots + 1);
+        let mut left = window.into_iter().peekable();
+        let mut right = other.window.into_iter().peekable();
+        loop {
+            let next = match (left.peek().map(|e| e.time), right.peek().map(|e| e.time)) {
+                (Some(l), Some(r)) if l == r => left.next().unwrap().try_combined(right.next().unwrap())?,
+                (Some(l), Some(r)) if l < r => left.next().unwrap(),
+                (Some(_), None) => left.next().unwrap(),
+                (_, Some(_)) => right.next().unwrap(),
+                (None, None) => break,
+            };
+            combined.push_back(next);
+            if combined.len() > slots {
+                combined.pop_front();
+            }
+        }
+        Ok(SlidingWindowMetrics {
+            start_t,
+            slot_dt,
+            slots,
+            window: combined,
+            metrics,
+        })
+    }
+}
+
+const HISTOGRAM_BITS: usize = 4;
+// rescale weights long before they could overflow an f64
+const MAX_HALF_LIVES: f64 = 64.0;
+
+/// A latency histogram where each sample's weight halves every `half_life` (in the units of
+/// `end_t`), so that quantiles reflect recent behaviour.
+///
+/// Buckets have the same 5 significant bits precision as `hdrhist::HDRHist`.
+#[derive(Debug, Clone)]
+pub struct DecayedHistogram {
+    half_life: u64,
+    landmark: u64,
+    weights: Vec<f64>,
+}
+
+impl DecayedHistogram {
+    pub fn new(half_life: u64) -> Self {
+        assert!(half_life > 0, "half_life must be positive");
+        Self {
+            half_life,
+            landmark: 0,
+            weights: vec![0f64; bucket_count(HISTOGRAM_BITS)],
+        }
+    }
+
+    /// Total weight of the recorded samples, as of the latest recorded sample.
+    pub fn total_weight(&self) -> f64 {
+        self.weights.iter().sum()
+    }
+
+    /// Upper bound of the `quantile` (in `[0, 1]`), `None` if the histogram is empty.
+    pub fn quantile(&self, quantile: f64) -> Option<u64> {
+        let total = self.total_weight();
+        if total == 0f64 {
+            return None;
+        }
+        let mut sum = 0f64;
+        for (bucket, weight) in self.weights.iter().enumerate() {
+            sum += weight;
+            if *weight > 0f64 && sum >= quantile * total {
+                return Some(bucket_value(bucket + 1, HISTOGRAM_BITS));
+            }
+        }
+        self.weights.iter().rposition(|w| *w > 0f64).map(|b| bucket_value(b + 1, HISTOGRAM_BITS))
+    }
+
+    fn rescale(&mut self, landmark: u64) {
+        let factor = (-((landmark - self.landmark) as f64) / self.half_life as f64).exp2();
+        for weight in self.weights.iter_mut() {
+            *weight *= factor;
+        }
+        self.landmark = landmark;
+    }
+}
+
+impl Metrics<u64> for DecayedHistogram {
+    #[inline(always)]
+    fn record(&mut self, begin_t: u64, end_t: u64) {
+        if end_t > self.landmark && (end_t - self.landmark) as f64 / self.half_life as f64 > MAX_HALF_LIVES {
+            self.rescale(end_t);
+        }
+        let exponent = (end_t as f64 - self.landmark as f64) / self.half_life as f64;
+        self.weights[bucket_index(end_t - begin_t, HISTOGRAM_BITS)] += exponent.exp2();
+    }
+
+    fn combined(self, other: Self) -> Self {
+        self.try_combined(other).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn try_combined(mut self, mut other: Self) -> Result<Self, CombineError> {
+        if self.half_life != other.half_life {
+            return Err(CombineError::mismatch("half_life", self.half_life, other.half_life));
+        }
+        if self.landmark < other.landmark {
+            self.rescale(other.landmark);
+        } else {
+            other.rescale(self.landmark);
+        }
+        for (weight, other_weight) in self.weights.iter_mut().zip(other.weights) {
+            *weight += other_weight;
+        }
+        Ok(self)
+    }
+}
+
+/// Quantiles and fractions are of the decayed weights.
+impl Distribution for DecayedHistogram {
+    fn quantile_upper_bounds(&self, quantiles: &[f64]) -> Vec<u64> {
+        quantiles.iter().map(|q| self.quantile(*q).unwrap_or(0)).collect()
+    }
+
+    fn ccdf_upper_bounds(&self) -> Vec<(u64, f64)> {
+        let total = self.total_weight();
+        let mut above = total;
+        let mut ccdf = Vec::new();
+        for (bucket, weight) in self.weights.iter().enumerate().filter(|&(_, weight)| *weight > 0f64) {
+            ccdf.push((bucket_value(bucket + 1, HISTOGRAM_BITS), above / total));
+            above -= weight;
+        }
+        ccdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use histogram::SortedSamples;
+
+    #[test]
+    fn combine_different_slot_lengths() {
+        let short = SlidingWindowMetrics::new(0u64, 10u64, 4, SortedSamples::new);
+        let long = SlidingWindowMetrics::new(0u64, 1_000u64, 4, SortedSamples::new);
+        match short.try_combined(long) {
+            Err(CombineError::Mismatch { what, .. }) => assert_eq!(what, "slot_dt"),
+            _ => panic!("expected a mismatch"),
+        }
+    }
+
+    #[test]
+    fn current_after_flushing() {
+        let mut window = SlidingWindowMetrics::new(10u64, 10u64, 2, SortedSamples::new);
+        assert!(window.current().is_none());
+        window.flush_until(5);
+        assert!(window.current().is_none());
+        window.flush_until(25);
+        assert_eq!(window.current().map(|m| m.len()), Some(0));
+        window.record(20, 26);
+        window.record(0, 36);
+        assert_eq!(window.current().map(|m| m.len()), Some(2));
+        window.flush_until(45);
+        assert_eq!(window.current().map(|m| m.len()), Some(1));
+    }
+}